@@ -1,4 +1,5 @@
--- Create the blockchain_metrics table
+-- Per-block chain metrics. Also brings databases created by the old init scripts up to date:
+-- sql/init_bk.sql used an INTEGER block_height and sql/alter_rich_info.sql added the block columns.
 CREATE TABLE IF NOT EXISTS blockchain_metrics (
     id SERIAL PRIMARY KEY,
     timestamp TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
@@ -11,14 +12,19 @@ CREATE TABLE IF NOT EXISTS blockchain_metrics (
     block_hash TEXT
 );
 
--- Add a unique constraint on block_height
-ALTER TABLE blockchain_metrics ADD CONSTRAINT unique_block_height UNIQUE (block_height);
+ALTER TABLE blockchain_metrics
+    ALTER COLUMN block_height TYPE BIGINT,
+    ADD COLUMN IF NOT EXISTS tx_count INTEGER,
+    ADD COLUMN IF NOT EXISTS block_size INTEGER,
+    ADD COLUMN IF NOT EXISTS block_timestamp BIGINT,
+    ADD COLUMN IF NOT EXISTS block_hash TEXT;
 
--- Create indexes for faster queries
+-- Older databases already carry this as the unique_block_height constraint, whose index has the same name
+CREATE UNIQUE INDEX IF NOT EXISTS unique_block_height ON blockchain_metrics (block_height);
 CREATE INDEX IF NOT EXISTS idx_blockchain_metrics_timestamp ON blockchain_metrics (timestamp);
 CREATE INDEX IF NOT EXISTS idx_blockchain_metrics_block_height ON blockchain_metrics (block_height);
 
--- Create a function to update the timestamp
+-- Keep the timestamp column current on updates
 CREATE OR REPLACE FUNCTION update_timestamp()
 RETURNS TRIGGER AS $$
 BEGIN
@@ -27,13 +33,12 @@ BEGIN
 END;
 $$ LANGUAGE plpgsql;
 
--- Create a trigger to automatically update the timestamp
+DROP TRIGGER IF EXISTS update_blockchain_metrics_timestamp ON blockchain_metrics;
 CREATE TRIGGER update_blockchain_metrics_timestamp
 BEFORE UPDATE ON blockchain_metrics
 FOR EACH ROW
 EXECUTE FUNCTION update_timestamp();
 
--- Create a function for upserting blockchain metrics
 CREATE OR REPLACE FUNCTION upsert_blockchain_metrics(
     p_block_height BIGINT,
     p_difficulty TEXT,
@@ -59,4 +64,3 @@ BEGIN
         timestamp = CURRENT_TIMESTAMP;
 END;
 $$ LANGUAGE plpgsql;
---If init in this, we can use a clean way to insert or retrieve / update data in the table
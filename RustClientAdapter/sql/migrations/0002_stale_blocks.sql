@@ -0,0 +1,11 @@
+-- Blocks that were replaced on the active chain by a reorganization
+CREATE TABLE IF NOT EXISTS stale_blocks (
+    id SERIAL PRIMARY KEY,
+    block_height BIGINT NOT NULL,
+    block_hash TEXT NOT NULL UNIQUE,
+    tx_count INTEGER,
+    block_size INTEGER,
+    block_timestamp BIGINT,
+    detected_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX IF NOT EXISTS idx_stale_blocks_block_height ON stale_blocks (block_height);
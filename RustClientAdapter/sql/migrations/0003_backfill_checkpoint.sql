@@ -0,0 +1,7 @@
+-- Resumable progress of the full-history backfill
+CREATE TABLE IF NOT EXISTS backfill_checkpoint (
+    name TEXT PRIMARY KEY,
+    next_height BIGINT NOT NULL,
+    target_height BIGINT NOT NULL,
+    updated_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,7 @@
+-- Last block processed by the live ingester, so restarts resume where they left off
+CREATE TABLE IF NOT EXISTS ingestion_state (
+    name TEXT PRIMARY KEY,
+    last_height BIGINT NOT NULL,
+    last_hash TEXT NOT NULL,
+    updated_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,17 @@
+-- Per-transaction details written by the ingester in RPC mode
+CREATE TABLE IF NOT EXISTS transactions (
+    id SERIAL PRIMARY KEY,
+    txid VARCHAR(64) UNIQUE,
+    block_height INT,
+    fee BIGINT,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+ALTER TABLE transactions
+    ADD COLUMN IF NOT EXISTS position INTEGER,
+    ADD COLUMN IF NOT EXISTS vsize INTEGER,
+    ADD COLUMN IF NOT EXISTS weight INTEGER,
+    ADD COLUMN IF NOT EXISTS input_count INTEGER,
+    ADD COLUMN IF NOT EXISTS output_count INTEGER,
+    ADD COLUMN IF NOT EXISTS is_segwit BOOLEAN,
+    ADD COLUMN IF NOT EXISTS is_taproot BOOLEAN;
+CREATE INDEX IF NOT EXISTS idx_transactions_block_height ON transactions (block_height);
@@ -0,0 +1,15 @@
+-- Per-block fee economics; feerates in sat/vB, amounts in satoshis
+CREATE TABLE IF NOT EXISTS block_fee_stats (
+    block_height BIGINT PRIMARY KEY,
+    block_hash TEXT NOT NULL,
+    total_fee BIGINT NOT NULL,
+    subsidy BIGINT NOT NULL,
+    min_feerate DOUBLE PRECISION NOT NULL,
+    feerate_p10 DOUBLE PRECISION NOT NULL,
+    feerate_p25 DOUBLE PRECISION NOT NULL,
+    feerate_p50 DOUBLE PRECISION NOT NULL,
+    feerate_p75 DOUBLE PRECISION NOT NULL,
+    feerate_p90 DOUBLE PRECISION NOT NULL,
+    max_feerate DOUBLE PRECISION NOT NULL,
+    updated_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,11 @@
+-- Periodic mempool samples; feerate histogram buckets are in sat/vB
+CREATE TABLE IF NOT EXISTS mempool_snapshots (
+    id SERIAL PRIMARY KEY,
+    sampled_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    tx_count BIGINT NOT NULL,
+    vsize BIGINT NOT NULL,
+    total_fee BIGINT NOT NULL,
+    min_relay_feerate DOUBLE PRECISION,
+    feerate_histogram JSONB NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_mempool_snapshots_sampled_at ON mempool_snapshots (sampled_at);
@@ -0,0 +1,8 @@
+-- Block header fields used by the block detail endpoints
+ALTER TABLE blockchain_metrics
+    ADD COLUMN IF NOT EXISTS prev_hash TEXT,
+    ADD COLUMN IF NOT EXISTS version INTEGER,
+    ADD COLUMN IF NOT EXISTS merkle_root TEXT,
+    ADD COLUMN IF NOT EXISTS nonce BIGINT,
+    ADD COLUMN IF NOT EXISTS bits BIGINT;
+CREATE INDEX IF NOT EXISTS idx_blockchain_metrics_block_hash ON blockchain_metrics (block_hash);
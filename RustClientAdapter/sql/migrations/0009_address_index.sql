@@ -0,0 +1,25 @@
+-- Address index built from ingested blocks in RPC mode
+CREATE TABLE IF NOT EXISTS address_outputs (
+    txid VARCHAR(64) NOT NULL,
+    vout INTEGER NOT NULL,
+    address TEXT NOT NULL,
+    value BIGINT NOT NULL,
+    block_height BIGINT NOT NULL,
+    PRIMARY KEY (txid, vout)
+);
+CREATE INDEX IF NOT EXISTS idx_address_outputs_address ON address_outputs (address, block_height);
+CREATE INDEX IF NOT EXISTS idx_address_outputs_block_height ON address_outputs (block_height);
+
+CREATE TABLE IF NOT EXISTS address_inputs (
+    txid VARCHAR(64) NOT NULL,
+    vin INTEGER NOT NULL,
+    address TEXT NOT NULL,
+    value BIGINT NOT NULL,
+    block_height BIGINT NOT NULL,
+    prev_txid VARCHAR(64) NOT NULL,
+    prev_vout INTEGER NOT NULL,
+    PRIMARY KEY (txid, vin)
+);
+CREATE INDEX IF NOT EXISTS idx_address_inputs_address ON address_inputs (address, block_height);
+CREATE INDEX IF NOT EXISTS idx_address_inputs_prevout ON address_inputs (prev_txid, prev_vout);
+CREATE INDEX IF NOT EXISTS idx_address_inputs_block_height ON address_inputs (block_height);
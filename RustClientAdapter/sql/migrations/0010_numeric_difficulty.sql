@@ -0,0 +1,39 @@
+-- Difficulty used to be stored as text holding the network difficulty at ingestion time.
+-- Store it as a number and recompute it from each block's own compact target; rows written
+-- before the header columns existed have no bits and keep their recorded value.
+ALTER TABLE blockchain_metrics
+    ALTER COLUMN difficulty TYPE DOUBLE PRECISION USING difficulty::DOUBLE PRECISION;
+
+-- difficulty = max target / target, with max target = 0xffff * 256^(0x1d - 3)
+-- and target = mantissa * 256^(exponent - 3)
+UPDATE blockchain_metrics
+SET difficulty = 65535 / (bits & 16777215)::DOUBLE PRECISION
+    * power(256::DOUBLE PRECISION, 29 - (bits >> 24))
+WHERE bits IS NOT NULL AND (bits & 16777215) > 0;
+
+DROP FUNCTION IF EXISTS upsert_blockchain_metrics(BIGINT, TEXT, INTEGER, INTEGER, INTEGER, BIGINT, TEXT);
+CREATE OR REPLACE FUNCTION upsert_blockchain_metrics(
+    p_block_height BIGINT,
+    p_difficulty DOUBLE PRECISION,
+    p_connection_count INTEGER,
+    p_tx_count INTEGER,
+    p_block_size INTEGER,
+    p_block_timestamp BIGINT,
+    p_block_hash TEXT
+) RETURNS VOID AS $$
+BEGIN
+    INSERT INTO blockchain_metrics (
+        block_height, difficulty, connection_count, tx_count, block_size, block_timestamp, block_hash
+    ) VALUES (
+        p_block_height, p_difficulty, p_connection_count, p_tx_count, p_block_size, p_block_timestamp, p_block_hash
+    )
+    ON CONFLICT (block_height) DO UPDATE SET
+        difficulty = EXCLUDED.difficulty,
+        connection_count = EXCLUDED.connection_count,
+        tx_count = EXCLUDED.tx_count,
+        block_size = EXCLUDED.block_size,
+        block_timestamp = EXCLUDED.block_timestamp,
+        block_hash = EXCLUDED.block_hash,
+        timestamp = CURRENT_TIMESTAMP;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,2 @@
+-- Listing every mempool entry is expensive, so most snapshots are taken without a histogram.
+ALTER TABLE mempool_snapshots ALTER COLUMN feerate_histogram DROP NOT NULL;
@@ -0,0 +1,7 @@
+-- Blocks whose inputs could not all be attributed to addresses because the node returned no
+-- prevout data for them; address balances count the outputs those inputs spend as unspent
+CREATE TABLE IF NOT EXISTS address_index_gaps (
+    block_height BIGINT PRIMARY KEY,
+    missing_inputs INTEGER NOT NULL,
+    recorded_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
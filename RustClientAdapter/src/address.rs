@@ -0,0 +1,131 @@
+use crate::data_provider::{AddressInfo, AddressTransaction, AddressUtxo};
+use crate::telemetry;
+use bitcoincore_rpc::bitcoin::{Address, Network};
+use std::str::FromStr;
+use thiserror::Error;
+use tokio_postgres::Client;
+
+/// Most UTXOs returned for one address
+const MAX_UTXOS: i64 = 1000;
+
+#[derive(Error, Debug)]
+pub enum AddressError {
+    #[error("Malformed address {address}: {reason}")]
+    Malformed { address: String, reason: String },
+    #[error("Address {address} is not valid on {network}, the network this explorer is configured for")]
+    WrongNetwork { address: String, network: Network },
+}
+
+/// Parses a base58 or bech32/bech32m address and checks it belongs to `network`.
+pub fn parse_address(address: &str, network: Network) -> Result<Address, AddressError> {
+    let unchecked = Address::from_str(address).map_err(|e| AddressError::Malformed {
+        address: address.to_string(),
+        reason: e.to_string(),
+    })?;
+    unchecked
+        .require_network(network)
+        .map_err(|_| AddressError::WrongNetwork {
+            address: address.to_string(),
+            network,
+        })
+}
+
+/// Answers address queries from `address_outputs` and `address_inputs`, which only cover the
+/// blocks ingested so far. The balance is the sum of indexed unspent outputs, so it stays correct
+/// when spends of outputs older than the index are seen. Unconfirmed activity is not indexed, and
+/// spends in the blocks listed in `address_index_gaps` are missing.
+pub async fn load_address_info(
+    client: &Client,
+    address: &str,
+    limit: i64,
+    offset: i64,
+) -> Result<AddressInfo, tokio_postgres::Error> {
+    let totals = telemetry::observe_db(
+        "load_address_info",
+        client.query_one(
+            "SELECT
+                (SELECT COALESCE(SUM(value), 0) FROM address_outputs WHERE address = $1)::BIGINT AS total_received,
+                (SELECT COALESCE(SUM(value), 0) FROM address_inputs WHERE address = $1)::BIGINT AS total_sent,
+                (SELECT COUNT(*) FROM (
+                    SELECT txid FROM address_outputs WHERE address = $1
+                    UNION
+                    SELECT txid FROM address_inputs WHERE address = $1
+                ) t) AS tx_count,
+                (SELECT COALESCE(SUM(o.value), 0) FROM address_outputs o
+                 WHERE o.address = $1 AND NOT EXISTS (
+                    SELECT 1 FROM address_inputs i WHERE i.prev_txid = o.txid AND i.prev_vout = o.vout
+                 ))::BIGINT AS balance",
+            &[&address],
+        ),
+    )
+    .await?;
+    let balance: i64 = totals.get("balance");
+    let total_received: i64 = totals.get("total_received");
+    let total_sent: i64 = totals.get("total_sent");
+    let tx_count: i64 = totals.get("tx_count");
+
+    let rows = telemetry::observe_db(
+        "load_address_info",
+        client.query(
+            "SELECT t.txid, t.block_height, m.block_timestamp, SUM(t.delta)::BIGINT AS value_change
+             FROM (
+                SELECT txid, block_height, value AS delta FROM address_outputs WHERE address = $1
+                UNION ALL
+                SELECT txid, block_height, -value AS delta FROM address_inputs WHERE address = $1
+             ) t
+             LEFT JOIN blockchain_metrics m ON m.block_height = t.block_height
+             GROUP BY t.txid, t.block_height, m.block_timestamp
+             ORDER BY t.block_height DESC, t.txid
+             LIMIT $2 OFFSET $3",
+            &[&address, &limit, &offset],
+        ),
+    )
+    .await?;
+    let transactions: Vec<AddressTransaction> = rows
+        .into_iter()
+        .map(|row| AddressTransaction {
+            txid: row.get("txid"),
+            block_height: row.get("block_height"),
+            block_time: row.get("block_timestamp"),
+            value_change: row.get("value_change"),
+        })
+        .collect();
+
+    let rows = telemetry::observe_db(
+        "load_address_info",
+        client.query(
+            "SELECT o.txid, o.vout, o.value, o.block_height
+             FROM address_outputs o
+             WHERE o.address = $1
+               AND NOT EXISTS (
+                SELECT 1 FROM address_inputs i WHERE i.prev_txid = o.txid AND i.prev_vout = o.vout
+               )
+             ORDER BY o.block_height DESC, o.txid, o.vout
+             LIMIT $2",
+            &[&address, &MAX_UTXOS],
+        ),
+    )
+    .await?;
+    let utxos: Vec<AddressUtxo> = rows
+        .into_iter()
+        .map(|row| AddressUtxo {
+            txid: row.get("txid"),
+            vout: row.get::<_, i32>("vout") as u32,
+            value: row.get("value"),
+            block_height: row.get("block_height"),
+        })
+        .collect();
+
+    let next_offset = Some(offset + transactions.len() as i64).filter(|next| *next < tx_count);
+    Ok(AddressInfo {
+        address: address.to_string(),
+        confirmed_balance: balance,
+        unconfirmed_balance: None,
+        total_received,
+        total_sent,
+        tx_count,
+        transactions,
+        utxos,
+        next_offset,
+    })
+}
@@ -0,0 +1,151 @@
+use crate::config::BackfillConfig;
+use crate::data_provider::BitcoinDataProvider;
+use crate::metrics::store_block;
+use crate::telemetry;
+use deadpool_postgres::Pool;
+use futures::stream::{self, StreamExt};
+use log::info;
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+use tokio_postgres::Client;
+
+const CHECKPOINT_NAME: &str = "blockchain_metrics";
+const CHUNK_SIZE: i64 = 100;
+const BATCH_SIZE: usize = 10;
+
+/// Walks `blockchain_metrics` from the configured start height up to the current tip,
+/// resuming from the checkpoint left by a previous run. Each block is written in its own
+/// transaction and the checkpoint only moves past a chunk once all of its blocks are committed,
+/// so a stored height always has all of its rows.
+pub async fn run_backfill(
+    provider: &BitcoinDataProvider,
+    pool: Pool,
+    config: &BackfillConfig,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let target_height = provider.get_block_count().await?;
+    let first_height = match load_checkpoint(&*pool.get().await?).await? {
+        Some(next_height) => std::cmp::max(next_height, config.start_height),
+        None => config.start_height,
+    };
+
+    if first_height > target_height {
+        info!("Backfill already complete up to height {}", target_height);
+        return Ok(());
+    }
+
+    info!(
+        "Starting backfill from height {} to {} with concurrency {}",
+        first_height, target_height, config.concurrency
+    );
+    let started = Instant::now();
+    let mut next_height = first_height;
+
+    while next_height <= target_height {
+        let chunk_end = std::cmp::min(next_height + CHUNK_SIZE - 1, target_height);
+        let mut connection = pool.get().await?;
+        let db: &mut Client = &mut connection;
+        let stored = get_stored_heights(db, next_height, chunk_end).await?;
+        let missing: Vec<i64> = (next_height..=chunk_end)
+            .filter(|height| !stored.contains(height))
+            .collect();
+
+        if !missing.is_empty() {
+            let connection_count = provider.get_connection_count().await? as i32;
+
+            // Fetch batches with bounded concurrency but keep the results in height order
+            let batches: Vec<Vec<i64>> = missing.chunks(BATCH_SIZE).map(<[i64]>::to_vec).collect();
+            let mut batches = stream::iter(batches)
+                .map(|heights| async move { provider.get_block_infos(&heights).await })
+                .buffered(config.concurrency.max(1));
+            while let Some(batch) = batches.next().await {
+                for block_info in batch? {
+                    let transaction = db.transaction().await?;
+                    store_block(&transaction, &block_info, connection_count).await?;
+                    transaction.commit().await?;
+                }
+            }
+        }
+
+        next_height = chunk_end + 1;
+        save_checkpoint(db, next_height, target_height).await?;
+        log_progress(first_height, next_height, target_height, started.elapsed());
+    }
+
+    info!("Backfill finished at height {}", target_height);
+    Ok(())
+}
+
+fn log_progress(first_height: i64, next_height: i64, target_height: i64, elapsed: Duration) {
+    let done = next_height - first_height;
+    let remaining = target_height + 1 - next_height;
+    let total = target_height + 1 - first_height;
+    let rate = done as f64 / elapsed.as_secs_f64().max(1e-3);
+    let eta = if rate > 0.0 {
+        Duration::from_secs_f64(remaining as f64 / rate)
+    } else {
+        Duration::ZERO
+    };
+    info!(
+        "Backfill progress: height {}/{} ({:.1}%), {:.2} blocks/s, ETA {}",
+        next_height - 1,
+        target_height,
+        done as f64 * 100.0 / total as f64,
+        rate,
+        format_duration(eta)
+    );
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{}h{:02}m{:02}s", secs / 3600, (secs % 3600) / 60, secs % 60)
+}
+
+async fn get_stored_heights(
+    client: &Client,
+    from_height: i64,
+    to_height: i64,
+) -> Result<HashSet<i64>, tokio_postgres::Error> {
+    let rows = telemetry::observe_db(
+        "get_stored_heights",
+        client.query(
+            "SELECT block_height FROM blockchain_metrics WHERE block_height BETWEEN $1 AND $2",
+            &[&from_height, &to_height],
+        ),
+    )
+    .await?;
+    Ok(rows.into_iter().map(|row| row.get("block_height")).collect())
+}
+
+async fn load_checkpoint(client: &Client) -> Result<Option<i64>, tokio_postgres::Error> {
+    let row = telemetry::observe_db(
+        "load_checkpoint",
+        client.query_opt(
+            "SELECT next_height FROM backfill_checkpoint WHERE name = $1",
+            &[&CHECKPOINT_NAME],
+        ),
+    )
+    .await?;
+    Ok(row.map(|row| row.get("next_height")))
+}
+
+async fn save_checkpoint(
+    client: &Client,
+    next_height: i64,
+    target_height: i64,
+) -> Result<(), tokio_postgres::Error> {
+    telemetry::observe_db(
+        "save_checkpoint",
+        client.execute(
+            "INSERT INTO backfill_checkpoint (name, next_height, target_height)
+             VALUES ($1, $2, $3)
+             ON CONFLICT (name)
+             DO UPDATE SET
+                next_height = EXCLUDED.next_height,
+                target_height = EXCLUDED.target_height,
+                updated_at = CURRENT_TIMESTAMP",
+            &[&CHECKPOINT_NAME, &next_height, &target_height],
+        ),
+    )
+    .await?;
+    Ok(())
+}
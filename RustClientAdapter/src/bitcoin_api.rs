@@ -1,14 +1,25 @@
+use crate::bitcoin_rpc::RpcTransaction;
+use crate::data_provider::{
+    block_subsidy, AddressInfo, AddressTransaction, AddressUtxo, BlockFeeStats, BlockInfo,
+    ChainSource, MempoolEntry, MempoolInfo, NotFoundError, ProviderError, ProviderResult,
+    TransactionDetail, TxStatus,
+};
+use crate::telemetry;
+use async_trait::async_trait;
+use bitcoincore_rpc::bitcoin::consensus::encode::{deserialize_hex, FromHexError};
+use bitcoincore_rpc::bitcoin::{Amount, Network, ScriptBuf, TxOut};
 use reqwest::Client;
 use serde_json::Value;
-use std::cell::RefCell;
-use std::error::Error;
 use std::time::{Duration, Instant};
 use thiserror::Error;
+use tokio::sync::Mutex;
 use tokio::time::sleep;
 
 const BASE_URL: &str = "https://blockchain.info/";
 const RATE_LIMIT: Duration = Duration::from_secs(10);
 const MAX_RETRIES: u32 = 3;
+/// Largest page `/unspent` serves
+const MAX_UNSPENT: u32 = 1000;
 
 #[derive(Error, Debug)]
 pub enum BitcoinApiError {
@@ -18,39 +29,47 @@ pub enum BitcoinApiError {
     JsonParseError(#[from] serde_json::Error),
     #[error("API response missing expected field: {0}")]
     MissingField(String),
+    #[error("Resource not found: {0}")]
+    NotFound(String),
     #[error("API rate limit exceeded")]
     RateLimitExceeded,
     #[error("Maximum retries exceeded")]
     MaxRetriesExceeded,
+    #[error("Failed to decode transaction: {0}")]
+    Decode(#[from] FromHexError),
 }
 
 pub struct BitcoinApi {
     client: Client,
-    last_request: RefCell<Instant>,
+    last_request: Mutex<Instant>,
 }
 
 impl BitcoinApi {
     pub fn new() -> Self {
         BitcoinApi {
             client: Client::new(),
-            last_request: RefCell::new(Instant::now() - RATE_LIMIT),
+            last_request: Mutex::new(Instant::now() - RATE_LIMIT),
         }
     }
 
     async fn make_request(&self, endpoint: &str) -> Result<String, BitcoinApiError> {
-        let mut last_request = self.last_request.borrow_mut();
+        let mut last_request = self.last_request.lock().await;
         let now = Instant::now();
         if now.duration_since(*last_request) < RATE_LIMIT {
             sleep(RATE_LIMIT - now.duration_since(*last_request)).await;
         }
 
-        let url = format!("{}{}?cors=true", BASE_URL, endpoint);
+        let separator = if endpoint.contains('?') { '&' } else { '?' };
+        let url = format!("{}{}{}cors=true", BASE_URL, endpoint, separator);
         let response = self.client.get(&url).send().await?;
         if response.status().is_success() {
             let body = response.text().await?;
             *last_request = Instant::now();
             Ok(body)
+        } else if response.status().as_u16() == 404 {
+            Err(BitcoinApiError::NotFound(endpoint.to_string()))
         } else if response.status().as_u16() == 429 {
+            telemetry::record_rate_limited();
             Err(BitcoinApiError::RateLimitExceeded)
         } else {
             Err(BitcoinApiError::RequestFailed(
@@ -59,25 +78,22 @@ impl BitcoinApi {
         }
     }
 
-    pub async fn get_difficulty(&self) -> Result<f64, Box<dyn Error>> {
+    pub async fn get_difficulty(&self) -> ProviderResult<f64> {
         let response = self.make_request("/q/getdifficulty").await?;
         Ok(response.parse()?)
     }
 
-    pub async fn get_block_count(&self) -> Result<i64, Box<dyn Error>> {
+    pub async fn get_block_count(&self) -> ProviderResult<i64> {
         let response = self.make_request("/q/getblockcount").await?;
         Ok(response.parse()?)
     }
 
-    pub async fn get_latest_hash(&self) -> Result<String, BitcoinApiError> {
-        self.make_request("/q/latesthash").await
-    }
-
     pub async fn get_block_info(&self, height: i64) -> Result<BlockInfo, BitcoinApiError> {
         let mut retries = 0;
         loop {
             match self.attempt_get_block_info(height).await {
                 Ok(block_info) => return Ok(block_info),
+                Err(e @ BitcoinApiError::NotFound(_)) => return Err(e),
                 Err(e) => {
                     if retries >= MAX_RETRIES {
                         log::error!("Max retries exceeded for block height {}: {:?}", height, e);
@@ -94,12 +110,39 @@ impl BitcoinApi {
             }
         }
     }
-    pub async fn get_mempool_info(&self) -> Result<Value, Box<dyn Error>> {
+    /// blockchain.info only lists the most recent unconfirmed transactions, so this is a sample
+    /// of the mempool rather than its full contents. The summary is built from that listing, so
+    /// the entries are always included.
+    pub async fn get_mempool_info(&self) -> ProviderResult<MempoolInfo> {
         let response = self
             .make_request("/unconfirmed-transactions?format=json")
             .await?;
         let json: Value = serde_json::from_str(&response)?;
-        Ok(json)
+        let txs = json["txs"]
+            .as_array()
+            .ok_or_else(|| BitcoinApiError::MissingField("txs array".to_string()))?;
+
+        let mut entries = Vec::with_capacity(txs.len());
+        for tx in txs {
+            let fee = tx["fee"]
+                .as_i64()
+                .ok_or_else(|| BitcoinApiError::MissingField("tx fee".to_string()))?;
+            let weight = tx["weight"]
+                .as_u64()
+                .ok_or_else(|| BitcoinApiError::MissingField("tx weight".to_string()))?;
+            entries.push(MempoolEntry {
+                fee,
+                vsize: weight.div_ceil(4) as u32,
+            });
+        }
+
+        Ok(MempoolInfo {
+            tx_count: entries.len() as u64,
+            vsize: entries.iter().map(|entry| u64::from(entry.vsize)).sum(),
+            total_fee: entries.iter().map(|entry| entry.fee).sum(),
+            min_relay_feerate: None,
+            entries: Some(entries),
+        })
     }
     async fn attempt_get_block_info(&self, height: i64) -> Result<BlockInfo, BitcoinApiError> {
         let response = self
@@ -107,38 +150,404 @@ impl BitcoinApi {
             .await?;
 
         let json: Value = serde_json::from_str(&response)?;
-        let block = &json["blocks"]
+        // Orphaned blocks at the same height are listed too
+        let block = json["blocks"]
             .as_array()
             .ok_or_else(|| BitcoinApiError::MissingField("blocks array".to_string()))?
-            .first()
-            .ok_or_else(|| BitcoinApiError::MissingField("first block".to_string()))?;
-
-        Ok(BlockInfo {
-            height,
-            hash: block["hash"]
-                .as_str()
-                .ok_or_else(|| BitcoinApiError::MissingField("hash".to_string()))?
-                .to_string(),
-            time: block["time"]
+            .iter()
+            .find(|block| block["main_chain"].as_bool() == Some(true))
+            .ok_or_else(|| BitcoinApiError::MissingField("main chain block".to_string()))?;
+        block_info_from_json(block)
+    }
+
+    pub async fn get_block_info_by_hash(&self, hash: &str) -> Result<BlockInfo, BitcoinApiError> {
+        let response = self
+            .make_request(&format!("/rawblock/{}?format=json", hash))
+            .await?;
+        let block: Value = serde_json::from_str(&response)?;
+        block_info_from_json(&block)
+    }
+
+    /// Decodes the raw transaction and takes prevouts and the block height from the JSON view,
+    /// which blockchain.info serves separately.
+    pub async fn get_transaction(&self, txid: &str) -> Result<TransactionDetail, BitcoinApiError> {
+        let raw = self
+            .make_request(&format!("/rawtx/{}?format=hex", txid))
+            .await?;
+        let tx: RpcTransaction = deserialize_hex(raw.trim())?;
+
+        let response = self
+            .make_request(&format!("/rawtx/{}?format=json", txid))
+            .await?;
+        let json: Value = serde_json::from_str(&response)?;
+        let inputs = json["inputs"]
+            .as_array()
+            .ok_or_else(|| BitcoinApiError::MissingField("inputs array".to_string()))?;
+        let prevouts: Vec<Option<TxOut>> = if tx.is_coinbase() {
+            vec![None; tx.input.len()]
+        } else {
+            inputs.iter().map(|input| prevout_from_json(&input["prev_out"])).collect()
+        };
+
+        let block_height = json["block_height"].as_i64();
+        let status = TxStatus {
+            confirmed: block_height.is_some(),
+            block_height,
+            ..TxStatus::default()
+        };
+        let network = ChainSource::network(self);
+        Ok(TransactionDetail::decode(&tx, &prevouts, network, status))
+    }
+
+    /// blockchain.info lists unconfirmed transactions first, so the unconfirmed balance is only
+    /// known when fetching the first page of history.
+    pub async fn get_address_info(
+        &self,
+        address: &str,
+        limit: i64,
+        offset: i64,
+    ) -> Result<AddressInfo, BitcoinApiError> {
+        let response = self
+            .make_request(&format!("/rawaddr/{}?limit={}&offset={}", address, limit, offset))
+            .await?;
+        let json: Value = serde_json::from_str(&response)?;
+        let field = |name: &str| {
+            json[name]
                 .as_i64()
-                .ok_or_else(|| BitcoinApiError::MissingField("time".to_string()))?,
-            n_tx: block["n_tx"]
-                .as_u64()
-                .ok_or_else(|| BitcoinApiError::MissingField("n_tx".to_string()))?
-                as u32,
-            size: block["size"]
-                .as_u64()
-                .ok_or_else(|| BitcoinApiError::MissingField("size".to_string()))?
-                as u32,
+                .ok_or_else(|| BitcoinApiError::MissingField(name.to_string()))
+        };
+        let tx_count = field("n_tx")?;
+        let total_received = field("total_received")?;
+        let total_sent = field("total_sent")?;
+        let final_balance = field("final_balance")?;
+
+        let transactions = json["txs"]
+            .as_array()
+            .ok_or_else(|| BitcoinApiError::MissingField("txs array".to_string()))?
+            .iter()
+            .map(|tx| {
+                let block_height = tx["block_height"].as_i64();
+                Ok(AddressTransaction {
+                    txid: tx["hash"]
+                        .as_str()
+                        .ok_or_else(|| BitcoinApiError::MissingField("tx hash".to_string()))?
+                        .to_string(),
+                    block_height,
+                    block_time: block_height.and(tx["time"].as_i64()),
+                    value_change: tx["result"]
+                        .as_i64()
+                        .ok_or_else(|| BitcoinApiError::MissingField("tx result".to_string()))?,
+                })
+            })
+            .collect::<Result<Vec<_>, BitcoinApiError>>()?;
+        let unconfirmed_balance = (offset == 0).then(|| {
+            transactions
+                .iter()
+                .filter(|tx| tx.block_height.is_none())
+                .map(|tx| tx.value_change)
+                .sum::<i64>()
+        });
+
+        let utxos = self.get_unspent_outputs(address, &transactions).await?;
+        let next_offset = Some(offset + transactions.len() as i64).filter(|next| *next < tx_count);
+        Ok(AddressInfo {
+            address: address.to_string(),
+            confirmed_balance: final_balance - unconfirmed_balance.unwrap_or(0),
+            unconfirmed_balance,
+            total_received,
+            total_sent,
+            tx_count,
+            transactions,
+            utxos,
+            next_offset,
         })
     }
+
+    /// Block heights are filled in from `history` where the funding transaction appears in it.
+    async fn get_unspent_outputs(
+        &self,
+        address: &str,
+        history: &[AddressTransaction],
+    ) -> Result<Vec<AddressUtxo>, BitcoinApiError> {
+        let response = match self
+            .make_request(&format!("/unspent?active={}&limit={}", address, MAX_UNSPENT))
+            .await
+        {
+            Ok(response) => response,
+            // Answered with a 500 "No free outputs to spend" when the address has no UTXOs
+            Err(BitcoinApiError::RequestFailed(e))
+                if e.status() == Some(reqwest::StatusCode::INTERNAL_SERVER_ERROR) =>
+            {
+                return Ok(Vec::new())
+            }
+            Err(e) => return Err(e),
+        };
+        let json: Value = serde_json::from_str(&response)?;
+        json["unspent_outputs"]
+            .as_array()
+            .ok_or_else(|| BitcoinApiError::MissingField("unspent_outputs array".to_string()))?
+            .iter()
+            .map(|utxo| {
+                let txid = utxo["tx_hash_big_endian"]
+                    .as_str()
+                    .ok_or_else(|| BitcoinApiError::MissingField("tx_hash_big_endian".to_string()))?
+                    .to_string();
+                let block_height = history
+                    .iter()
+                    .find(|tx| tx.txid == txid)
+                    .and_then(|tx| tx.block_height);
+                Ok(AddressUtxo {
+                    vout: utxo["tx_output_n"]
+                        .as_u64()
+                        .ok_or_else(|| BitcoinApiError::MissingField("tx_output_n".to_string()))?
+                        as u32,
+                    value: utxo["value"]
+                        .as_i64()
+                        .ok_or_else(|| BitcoinApiError::MissingField("value".to_string()))?,
+                    txid,
+                    block_height,
+                })
+            })
+            .collect()
+    }
+}
+
+fn prevout_from_json(prev_out: &Value) -> Option<TxOut> {
+    let value = prev_out["value"].as_u64()?;
+    let script_pubkey = ScriptBuf::from_hex(prev_out["script"].as_str()?).ok()?;
+    Some(TxOut {
+        value: Amount::from_sat(value),
+        script_pubkey,
+    })
+}
+
+fn block_info_from_json(block: &Value) -> Result<BlockInfo, BitcoinApiError> {
+    let height = block["height"]
+        .as_i64()
+        .ok_or_else(|| BitcoinApiError::MissingField("height".to_string()))?;
+
+    Ok(BlockInfo {
+        height,
+        hash: block["hash"]
+            .as_str()
+            .ok_or_else(|| BitcoinApiError::MissingField("hash".to_string()))?
+            .to_string(),
+        prev_hash: block["prev_block"]
+            .as_str()
+            .ok_or_else(|| BitcoinApiError::MissingField("prev_block".to_string()))?
+            .to_string(),
+        version: block["ver"]
+            .as_i64()
+            .ok_or_else(|| BitcoinApiError::MissingField("ver".to_string()))?
+            as i32,
+        merkle_root: block["mrkl_root"]
+            .as_str()
+            .ok_or_else(|| BitcoinApiError::MissingField("mrkl_root".to_string()))?
+            .to_string(),
+        nonce: block["nonce"]
+            .as_u64()
+            .ok_or_else(|| BitcoinApiError::MissingField("nonce".to_string()))?
+            as u32,
+        bits: block["bits"]
+            .as_u64()
+            .ok_or_else(|| BitcoinApiError::MissingField("bits".to_string()))?
+            as u32,
+        timestamp: block["time"]
+            .as_i64()
+            .ok_or_else(|| BitcoinApiError::MissingField("time".to_string()))?,
+        tx_count: block["n_tx"]
+            .as_u64()
+            .ok_or_else(|| BitcoinApiError::MissingField("n_tx".to_string()))?
+            as u32,
+        size: block["size"]
+            .as_u64()
+            .ok_or_else(|| BitcoinApiError::MissingField("size".to_string()))?
+            as u32,
+        transactions: Vec::new(),
+        fee_stats: fee_stats_from_block(height, block)?,
+    })
+}
+
+/// Maps 404 responses to `NotFoundError` so callers can tell them apart.
+fn into_provider_error(e: BitcoinApiError, what: impl FnOnce() -> String) -> ProviderError {
+    match e {
+        BitcoinApiError::NotFound(_) => Box::new(NotFoundError(what())),
+        other => Box::new(other),
+    }
 }
 
-#[derive(Debug)]
-pub struct BlockInfo {
-    pub height: i64,
-    pub hash: String,
-    pub time: i64,
-    pub n_tx: u32,
-    pub size: u32,
+/// Computes the same statistics as bitcoind's `getblockstats` from a blockchain.info block.
+fn fee_stats_from_block(height: i64, block: &Value) -> Result<BlockFeeStats, BitcoinApiError> {
+    let txs = block["tx"]
+        .as_array()
+        .ok_or_else(|| BitcoinApiError::MissingField("tx array".to_string()))?;
+
+    // (feerate in whole sat/vB, weight), skipping the coinbase. Truncated like getblockstats so
+    // both backends store the same feerates
+    let mut rates = Vec::with_capacity(txs.len().saturating_sub(1));
+    let mut total_fee = 0;
+    for tx in txs.iter().skip(1) {
+        let fee = tx["fee"]
+            .as_i64()
+            .ok_or_else(|| BitcoinApiError::MissingField("tx fee".to_string()))?;
+        let weight = tx["weight"]
+            .as_i64()
+            .ok_or_else(|| BitcoinApiError::MissingField("tx weight".to_string()))?;
+        total_fee += fee;
+        if weight > 0 {
+            rates.push((fee * 4 / weight, weight));
+        }
+    }
+    rates.sort_unstable();
+
+    // Like getblockstats, a percentile is the feerate of the transaction whose cumulative
+    // weight first reaches that fraction of the total weight
+    let total_weight: i64 = rates.iter().map(|(_, weight)| weight).sum();
+    let mut feerate_percentiles = [0.0; 5];
+    for (slot, fraction) in feerate_percentiles.iter_mut().zip([0.1, 0.25, 0.5, 0.75, 0.9]) {
+        let threshold = total_weight as f64 * fraction;
+        let mut cumulative = 0;
+        for (rate, weight) in &rates {
+            cumulative += weight;
+            if cumulative as f64 >= threshold {
+                *slot = *rate as f64;
+                break;
+            }
+        }
+    }
+
+    Ok(BlockFeeStats {
+        total_fee,
+        subsidy: block_subsidy(height),
+        min_feerate: rates.first().map_or(0.0, |(rate, _)| *rate as f64),
+        max_feerate: rates.last().map_or(0.0, |(rate, _)| *rate as f64),
+        feerate_percentiles,
+    })
+}
+
+impl Default for BitcoinApi {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl ChainSource for BitcoinApi {
+    fn backend(&self) -> &'static str {
+        "blockchain_info"
+    }
+
+    fn network(&self) -> Network {
+        // blockchain.info only serves mainnet, so other networks are rejected at startup and
+        // addresses are validated against the configured network
+        Network::Bitcoin
+    }
+
+    async fn get_block_count(&self) -> ProviderResult<i64> {
+        BitcoinApi::get_block_count(self).await
+    }
+
+    async fn get_block_hash(&self, height: i64) -> ProviderResult<String> {
+        Ok(ChainSource::get_block_info(self, height).await?.hash)
+    }
+
+    async fn get_block_info(&self, height: i64) -> ProviderResult<BlockInfo> {
+        BitcoinApi::get_block_info(self, height)
+            .await
+            .map_err(|e| into_provider_error(e, || format!("Block at height {}", height)))
+    }
+
+    async fn get_block_info_by_hash(&self, hash: &str) -> ProviderResult<BlockInfo> {
+        BitcoinApi::get_block_info_by_hash(self, hash)
+            .await
+            .map_err(|e| into_provider_error(e, || format!("Block {}", hash)))
+    }
+
+    async fn get_difficulty(&self) -> ProviderResult<f64> {
+        BitcoinApi::get_difficulty(self).await
+    }
+
+    async fn get_connection_count(&self) -> ProviderResult<u64> {
+        // API doesn't provide connection count, return 0
+        Ok(0)
+    }
+
+    async fn get_mempool_info(&self, _with_entries: bool) -> ProviderResult<MempoolInfo> {
+        BitcoinApi::get_mempool_info(self).await
+    }
+
+    async fn get_raw_block(&self, hash: &str) -> ProviderResult<String> {
+        self.make_request(&format!("/rawblock/{}?format=hex", hash))
+            .await
+            .map_err(|e| into_provider_error(e, || format!("Block {}", hash)))
+    }
+
+    async fn get_raw_transaction(&self, txid: &str) -> ProviderResult<String> {
+        self.make_request(&format!("/rawtx/{}?format=hex", txid))
+            .await
+            .map_err(|e| into_provider_error(e, || format!("Transaction {}", txid)))
+    }
+
+    async fn get_transaction(&self, txid: &str) -> ProviderResult<TransactionDetail> {
+        BitcoinApi::get_transaction(self, txid)
+            .await
+            .map_err(|e| into_provider_error(e, || format!("Transaction {}", txid)))
+    }
+
+    async fn get_address_info(
+        &self,
+        address: &str,
+        limit: i64,
+        offset: i64,
+    ) -> ProviderResult<Option<AddressInfo>> {
+        BitcoinApi::get_address_info(self, address, limit, offset)
+            .await
+            .map(Some)
+            .map_err(|e| into_provider_error(e, || format!("Address {}", address)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn fee_stats_weight_feerate_percentiles() {
+        let block = json!({
+            "tx": [
+                { "fee": 0, "weight": 800 },
+                { "fee": 1099, "weight": 400 },
+                { "fee": 4150, "weight": 800 },
+                { "fee": 561, "weight": 400 },
+            ]
+        });
+        let stats = fee_stats_from_block(840_000, &block).unwrap();
+        assert_eq!(stats.total_fee, 5810);
+        assert_eq!(stats.subsidy, 312_500_000);
+        assert_eq!(stats.min_feerate, 5.0);
+        assert_eq!(stats.max_feerate, 20.0);
+        // Weights 400 @ 5.61, 400 @ 10.99 and 800 @ 20.75 sat/vB, truncated like getblockstats;
+        // the 25th percentile lands exactly on the end of the first transaction
+        assert_eq!(stats.feerate_percentiles, [5.0, 5.0, 10.0, 20.0, 20.0]);
+    }
+
+    #[test]
+    fn fee_stats_coinbase_only_block() {
+        let block = json!({ "tx": [{ "fee": 0, "weight": 800 }] });
+        let stats = fee_stats_from_block(0, &block).unwrap();
+        assert_eq!(stats.total_fee, 0);
+        assert_eq!(stats.subsidy, 5_000_000_000);
+        assert_eq!(stats.min_feerate, 0.0);
+        assert_eq!(stats.feerate_percentiles, [0.0; 5]);
+    }
+
+    #[test]
+    fn fee_stats_requires_fee_and_weight() {
+        let block = json!({ "tx": [{ "fee": 0, "weight": 800 }, { "weight": 400 }] });
+        assert!(matches!(
+            fee_stats_from_block(1, &block),
+            Err(BitcoinApiError::MissingField(_))
+        ));
+    }
 }
@@ -1,62 +1,759 @@
-use bitcoincore_rpc::{Auth, Client, RpcApi};
-use bitcoincore_rpc::bitcoin::consensus::encode::serialize;
-use std::sync::Arc;
-use log::{info, error};
-
-pub type RpcClient = Arc<Client>;
+use crate::config::{RpcAuth, RpcConfig};
+use crate::data_provider::{
+    BlockFeeStats, BlockInfo, ChainSource, InputInfo, MempoolEntry, MempoolInfo, NotFoundError,
+    OutputInfo, ProviderError, ProviderResult, TransactionDetail, TransactionInfo, TxStatus,
+};
+use async_trait::async_trait;
+use log::{debug, info, warn};
+use bitcoincore_rpc::bitcoin::consensus::encode::{deserialize_hex, FromHexError};
+use bitcoincore_rpc::bitcoin::{BlockHash, Network, TxOut, Txid};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use thiserror::Error;
+use tokio::sync::OnceCell;
+use tokio::time::{sleep, Duration};
 
 pub use bitcoincore_rpc::bitcoin::Transaction as RpcTransaction;
 
-pub struct BlockInfo {
-    pub height: i64,
-    pub hash: String,
-    pub time: i64,
-    pub n_tx: u32,
-    pub size: u32,
+const TAPROOT_SCRIPT_TYPE: &str = "witness_v1_taproot";
+/// bitcoind error codes for unknown blocks/transactions and out-of-range heights
+const RPC_INVALID_ADDRESS_OR_KEY: i64 = -5;
+const RPC_INVALID_PARAMETER: i64 = -8;
+/// `getrawmempool true` lists every mempool entry, which takes far longer than other calls
+const RAW_MEMPOOL_TIMEOUT: Duration = Duration::from_secs(120);
+
+#[derive(Error, Debug)]
+pub enum RpcError {
+    #[error("HTTP request failed: {0}")]
+    RequestFailed(#[from] reqwest::Error),
+    #[error("Failed to parse JSON: {0}")]
+    JsonParseError(#[from] serde_json::Error),
+    #[error("bitcoind returned HTTP status {0}")]
+    HttpStatus(reqwest::StatusCode),
+    #[error("RPC error {code}: {message}")]
+    Rpc { code: i64, message: String },
+    #[error("Missing response for request id {0}")]
+    MissingResponse(u64),
+    #[error("Failed to read RPC cookie file: {0}")]
+    CookieFile(#[from] std::io::Error),
+    #[error("Malformed RPC cookie file")]
+    MalformedCookie,
+    #[error("bitcoind is on chain {actual}, expected {expected}")]
+    WrongNetwork { expected: String, actual: String },
+    #[error("Failed to decode transaction: {0}")]
+    Decode(#[from] FromHexError),
+}
+
+#[derive(Deserialize)]
+struct RpcResponseError {
+    code: i64,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct RpcResponse {
+    result: Option<Value>,
+    error: Option<RpcResponseError>,
+    id: u64,
+}
+
+impl RpcResponse {
+    fn into_result<T: DeserializeOwned>(self) -> Result<T, RpcError> {
+        if let Some(error) = self.error {
+            return Err(RpcError::Rpc {
+                code: error.code,
+                message: error.message,
+            });
+        }
+        Ok(serde_json::from_value(self.result.unwrap_or(Value::Null))?)
+    }
+}
+
+/// Async JSON-RPC client for bitcoind that supports batching several calls into one round-trip.
+pub struct RpcClient {
+    http: reqwest::Client,
+    url: String,
+    auth: RpcAuth,
+    next_id: AtomicU64,
+}
+
+#[derive(Deserialize)]
+struct BlockchainInfo {
+    chain: String,
+}
+
+impl RpcClient {
+    pub fn new(config: &RpcConfig) -> Result<Self, RpcError> {
+        Ok(RpcClient {
+            http: reqwest::Client::builder().timeout(config.timeout).build()?,
+            url: config.url.clone(),
+            auth: config.auth.clone(),
+            next_id: AtomicU64::new(0),
+        })
+    }
+
+    async fn credentials(&self) -> Result<(String, String), RpcError> {
+        match &self.auth {
+            RpcAuth::UserPass { user, password } => Ok((user.clone(), password.clone())),
+            RpcAuth::CookieFile(path) => {
+                let cookie = tokio::fs::read_to_string(path).await?;
+                let (user, password) = cookie
+                    .trim()
+                    .split_once(':')
+                    .ok_or(RpcError::MalformedCookie)?;
+                Ok((user.to_string(), password.to_string()))
+            }
+        }
+    }
+
+    pub async fn call<T: DeserializeOwned>(&self, method: &str, params: &[Value]) -> Result<T, RpcError> {
+        self.call_with(method, params, None).await
+    }
+
+    /// Like `call`, but waits up to `timeout` instead of the configured RPC timeout.
+    pub async fn call_with_timeout<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: &[Value],
+        timeout: Duration,
+    ) -> Result<T, RpcError> {
+        self.call_with(method, params, Some(timeout)).await
+    }
+
+    async fn call_with<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: &[Value],
+        timeout: Option<Duration>,
+    ) -> Result<T, RpcError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let request = json!({ "jsonrpc": "1.0", "id": id, "method": method, "params": params });
+        let response: RpcResponse = self.send(&request, timeout).await?;
+        response.into_result()
+    }
+
+    /// Sends all calls in a single HTTP request; results are returned in the order of `calls`.
+    pub async fn batch<T: DeserializeOwned>(
+        &self,
+        calls: &[(&str, Vec<Value>)],
+    ) -> Result<Vec<T>, RpcError> {
+        self.batch_results(calls).await?.into_iter().collect()
+    }
+
+    /// Like `batch`, but keeps the outcome of every call so one failing call does not fail the
+    /// others. Only transport errors fail the whole batch.
+    pub async fn batch_results<T: DeserializeOwned>(
+        &self,
+        calls: &[(&str, Vec<Value>)],
+    ) -> Result<Vec<Result<T, RpcError>>, RpcError> {
+        if calls.is_empty() {
+            return Ok(Vec::new());
+        }
+        let first_id = self
+            .next_id
+            .fetch_add(calls.len() as u64, Ordering::Relaxed);
+        let requests: Vec<Value> = calls
+            .iter()
+            .enumerate()
+            .map(|(i, (method, params))| {
+                json!({ "jsonrpc": "1.0", "id": first_id + i as u64, "method": method, "params": params })
+            })
+            .collect();
+
+        let mut responses: HashMap<u64, RpcResponse> = self
+            .send::<Vec<RpcResponse>>(&Value::Array(requests), None)
+            .await?
+            .into_iter()
+            .map(|response| (response.id, response))
+            .collect();
+        Ok((0..calls.len() as u64)
+            .map(|i| {
+                responses
+                    .remove(&(first_id + i))
+                    .ok_or(RpcError::MissingResponse(first_id + i))?
+                    .into_result()
+            })
+            .collect())
+    }
+
+    async fn send<T: DeserializeOwned>(
+        &self,
+        body: &Value,
+        timeout: Option<Duration>,
+    ) -> Result<T, RpcError> {
+        let (user, password) = self.credentials().await?;
+        let mut request = self
+            .http
+            .post(&self.url)
+            .basic_auth(user, Some(password))
+            .json(body);
+        if let Some(timeout) = timeout {
+            request = request.timeout(timeout);
+        }
+        let response = request.send().await?;
+        // bitcoind reports RPC errors with a 500 status but a regular JSON-RPC body
+        let status = response.status();
+        let text = response.text().await?;
+        serde_json::from_str(&text).map_err(|e| {
+            if status.is_success() {
+                RpcError::JsonParseError(e)
+            } else {
+                RpcError::HttpStatus(status)
+            }
+        })
+    }
+}
+
+/// Block as returned by `getblock` with verbosity 3 (verbosity 2 on nodes older than v25,
+/// which only lacks the input `prevout` fields).
+#[derive(Deserialize)]
+struct VerboseBlock {
+    hash: String,
+    height: i64,
+    #[serde(default)]
+    previousblockhash: String,
+    version: i32,
+    merkleroot: String,
+    nonce: u32,
+    #[serde(deserialize_with = "deserialize_hex_u32")]
+    bits: u32,
+    time: i64,
+    size: u32,
+    tx: Vec<VerboseTransaction>,
 }
 
-pub fn connect_to_bitcoin_rpc() -> RpcClient {
-    Arc::new(
-        Client::new(
-            "http://127.0.0.1:8332",
-            Auth::UserPass("myrpcuser".to_string(), "myrpcpassword".to_string()),
-        )
-        .unwrap(),
-    )
+#[derive(Deserialize)]
+struct VerboseTransaction {
+    txid: String,
+    vsize: u32,
+    weight: u32,
+    /// Fee in BTC, omitted for the coinbase or when undo data is unavailable
+    fee: Option<f64>,
+    vin: Vec<VerboseInput>,
+    vout: Vec<VerboseOutput>,
 }
 
-pub fn get_block_count(rpc: &RpcClient) -> Result<i32, bitcoincore_rpc::Error> {
-    rpc.get_block_count().map(|count| count as i32)
+/// `txid` and `vout` are absent for the coinbase input.
+#[derive(Deserialize)]
+struct VerboseInput {
+    txid: Option<String>,
+    vout: Option<u32>,
+    #[serde(default)]
+    txinwitness: Vec<String>,
+    prevout: Option<VerboseOutput>,
 }
 
-pub fn get_block_hash(rpc: &RpcClient, block_height: i32) -> Result<bitcoincore_rpc::bitcoin::BlockHash, bitcoincore_rpc::Error> {
-    rpc.get_block_hash(block_height as u64)
+#[derive(Deserialize)]
+struct VerboseOutput {
+    /// BTC
+    value: f64,
+    #[serde(rename = "scriptPubKey")]
+    script_pub_key: ScriptPubKey,
 }
 
-pub fn get_block(rpc: &RpcClient, block_hash: &bitcoincore_rpc::bitcoin::BlockHash) -> Result<bitcoincore_rpc::bitcoin::Block, bitcoincore_rpc::Error> {
-    rpc.get_block(block_hash)
+#[derive(Deserialize)]
+struct ScriptPubKey {
+    #[serde(rename = "type")]
+    script_type: String,
+    address: Option<String>,
 }
 
-pub fn get_difficulty(rpc: &RpcClient) -> Result<f64, bitcoincore_rpc::Error> {
-    rpc.get_difficulty()
+/// Subset of `getblockstats`; feerates are in sat/vB and fees in satoshis.
+#[derive(Deserialize)]
+struct BlockStats {
+    totalfee: i64,
+    subsidy: i64,
+    minfeerate: f64,
+    maxfeerate: f64,
+    feerate_percentiles: [f64; 5],
 }
 
-pub fn get_mempool_info(rpc: &RpcClient) -> Result<bitcoincore_rpc::jsonrpc::serde_json::Value, bitcoincore_rpc::Error> {
-    rpc.call("getmempoolinfo", &[])
+impl From<BlockStats> for BlockFeeStats {
+    fn from(stats: BlockStats) -> Self {
+        BlockFeeStats {
+            total_fee: stats.totalfee,
+            subsidy: stats.subsidy,
+            min_feerate: stats.minfeerate,
+            max_feerate: stats.maxfeerate,
+            feerate_percentiles: stats.feerate_percentiles,
+        }
+    }
 }
-pub fn get_connection_count(rpc: &RpcClient) -> Result<u64, bitcoincore_rpc::Error> {
-    rpc.call("getconnectioncount", &[])
+
+/// Subset of `getmempoolinfo`; `total_fee` is only reported by bitcoind v23 and later.
+#[derive(Deserialize)]
+struct MempoolSummary {
+    size: u64,
+    bytes: u64,
+    total_fee: Option<f64>,
+    /// BTC/kvB
+    minrelaytxfee: f64,
+}
+
+#[derive(Deserialize)]
+struct RawMempoolEntry {
+    vsize: u32,
+    fees: RawMempoolFees,
+}
+
+#[derive(Deserialize)]
+struct RawMempoolFees {
+    base: f64,
+}
+
+fn deserialize_hex_u32<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    let hex = String::deserialize(deserializer)?;
+    u32::from_str_radix(&hex, 16).map_err(serde::de::Error::custom)
+}
+
+fn btc_to_sats(amount: f64) -> i64 {
+    (amount * 100_000_000.0).round() as i64
+}
+
+impl VerboseBlock {
+    fn into_info(self, fee_stats: BlockFeeStats) -> BlockInfo {
+        let tx_count = self.tx.len() as u32;
+        let transactions = self
+            .tx
+            .into_iter()
+            .enumerate()
+            .map(|(position, tx)| tx.into_info(position as u32))
+            .collect();
+        BlockInfo {
+            height: self.height,
+            hash: self.hash,
+            prev_hash: self.previousblockhash,
+            version: self.version,
+            merkle_root: self.merkleroot,
+            nonce: self.nonce,
+            bits: self.bits,
+            timestamp: self.time,
+            tx_count,
+            size: self.size,
+            transactions,
+            fee_stats,
+        }
+    }
+}
+
+impl VerboseTransaction {
+    fn into_info(self, position: u32) -> TransactionInfo {
+        let is_segwit = self.vin.iter().any(|input| !input.txinwitness.is_empty());
+        let is_taproot = self
+            .vout
+            .iter()
+            .chain(self.vin.iter().filter_map(|input| input.prevout.as_ref()))
+            .any(|output| output.script_pub_key.script_type == TAPROOT_SCRIPT_TYPE);
+        let missing_prevouts = self
+            .vin
+            .iter()
+            .filter(|input| input.txid.is_some() && input.prevout.is_none())
+            .count() as u32;
+        let inputs = self
+            .vin
+            .iter()
+            .enumerate()
+            .filter_map(|(vin, input)| {
+                let prevout = input.prevout.as_ref()?;
+                Some(InputInfo {
+                    vin: vin as u32,
+                    prev_txid: input.txid.clone()?,
+                    prev_vout: input.vout?,
+                    value: btc_to_sats(prevout.value),
+                    address: prevout.script_pub_key.address.clone(),
+                })
+            })
+            .collect();
+        let outputs = self
+            .vout
+            .iter()
+            .map(|output| OutputInfo {
+                value: btc_to_sats(output.value),
+                address: output.script_pub_key.address.clone(),
+            })
+            .collect();
+        TransactionInfo {
+            txid: self.txid,
+            position,
+            vsize: self.vsize,
+            weight: self.weight,
+            fee: self.fee.map(btc_to_sats),
+            input_count: self.vin.len() as u32,
+            output_count: self.vout.len() as u32,
+            is_segwit,
+            is_taproot,
+            inputs,
+            missing_prevouts,
+            outputs,
+        }
+    }
+}
+
+/// Connects to bitcoind and checks that it is on the configured network, retrying while it is
+/// unreachable or still warming up. If it does not answer in time the client is returned anyway,
+/// so the service starts degraded and the check runs again before the first block count.
+pub async fn connect_to_bitcoin_rpc(config: &RpcConfig) -> Result<BitcoinRpc, RpcError> {
+    let rpc = BitcoinRpc::new(RpcClient::new(config)?, config.network);
+    let mut retries = 5;
+    let mut delay = Duration::from_secs(1);
+
+    loop {
+        match rpc.ensure_chain().await {
+            Ok(()) => {
+                info!(
+                    "Connected to bitcoind at {} on chain {}",
+                    config.url,
+                    config.network.to_core_arg()
+                );
+                return Ok(rpc);
+            }
+            Err(e @ RpcError::WrongNetwork { .. }) => return Err(e),
+            Err(e) if retries == 0 => {
+                warn!(
+                    "bitcoind at {} is not available ({}), starting without it",
+                    config.url, e
+                );
+                return Ok(rpc);
+            }
+            Err(e) => {
+                warn!(
+                    "bitcoind at {} is not available: {}. Retrying in {:?}...",
+                    config.url, e, delay
+                );
+                sleep(delay).await;
+                retries -= 1;
+                delay *= 2;
+            }
+        }
+    }
+}
+
+async fn check_chain(rpc: &RpcClient, network: Network) -> Result<(), RpcError> {
+    let info: BlockchainInfo = rpc.call("getblockchaininfo", &[]).await?;
+    let expected = network.to_core_arg();
+    if info.chain != expected {
+        return Err(RpcError::WrongNetwork {
+            expected: expected.to_string(),
+            actual: info.chain,
+        });
+    }
+    Ok(())
+}
+
+pub async fn get_block_count(rpc: &RpcClient) -> Result<i64, RpcError> {
+    rpc.call("getblockcount", &[]).await
+}
+
+pub async fn get_block_hash(rpc: &RpcClient, block_height: i64) -> Result<String, RpcError> {
+    rpc.call("getblockhash", &[json!(block_height)]).await
+}
+
+pub async fn get_difficulty(rpc: &RpcClient) -> Result<f64, RpcError> {
+    rpc.call("getdifficulty", &[]).await
 }
 
-pub fn get_block_info(rpc: &RpcClient, height: i64) -> Result<BlockInfo, bitcoincore_rpc::Error> {
-    let hash = get_block_hash(rpc, height as i32)?;
-    let block = get_block(rpc, &hash)?;
-    let size = serialize(&block).len() as u32;
-    Ok(BlockInfo {
-        height,
-        hash: hash.to_string(),
-        time: block.header.time as i64,
-        n_tx: block.txdata.len() as u32,
-        size,
+/// Summarizes the mempool with `getmempoolinfo`. The entries come from `getrawmempool`, which
+/// bitcoind answers while holding the mempool lock, so they are only listed when asked for or
+/// when the node is too old to report the total fee. A failed listing leaves them out.
+pub async fn get_mempool_info(rpc: &RpcClient, with_entries: bool) -> Result<MempoolInfo, RpcError> {
+    let summary: MempoolSummary = rpc.call("getmempoolinfo", &[]).await?;
+    let entries = if with_entries || summary.total_fee.is_none() {
+        match get_mempool_entries(rpc).await {
+            Ok(entries) => Some(entries),
+            Err(e) if summary.total_fee.is_some() => {
+                warn!("Failed to list mempool entries: {}", e);
+                None
+            }
+            Err(e) => return Err(e),
+        }
+    } else {
+        None
+    };
+    let total_fee = match (summary.total_fee, &entries) {
+        (Some(total_fee), _) => btc_to_sats(total_fee),
+        (None, entries) => entries.iter().flatten().map(|entry| entry.fee).sum(),
+    };
+    Ok(MempoolInfo {
+        tx_count: summary.size,
+        vsize: summary.bytes,
+        total_fee,
+        // BTC/kvB -> sat/vB
+        min_relay_feerate: Some(summary.minrelaytxfee * 100_000.0),
+        entries,
     })
 }
+
+async fn get_mempool_entries(rpc: &RpcClient) -> Result<Vec<MempoolEntry>, RpcError> {
+    let raw_mempool: HashMap<String, RawMempoolEntry> = rpc
+        .call_with_timeout("getrawmempool", &[json!(true)], RAW_MEMPOOL_TIMEOUT)
+        .await?;
+    Ok(raw_mempool
+        .into_values()
+        .map(|entry| MempoolEntry {
+            fee: btc_to_sats(entry.fees.base),
+            vsize: entry.vsize,
+        })
+        .collect())
+}
+
+pub async fn get_connection_count(rpc: &RpcClient) -> Result<u64, RpcError> {
+    rpc.call("getconnectioncount", &[]).await
+}
+
+fn get_block_params(hash: &str) -> Vec<Value> {
+    vec![json!(hash), json!(3)]
+}
+
+/// `getblockstats` accepts either a height or a block hash.
+fn get_block_stats_params(hash_or_height: Value) -> Vec<Value> {
+    vec![
+        hash_or_height,
+        json!(["totalfee", "subsidy", "minfeerate", "maxfeerate", "feerate_percentiles"]),
+    ]
+}
+
+pub async fn get_block_info(rpc: &RpcClient, height: i64) -> Result<BlockInfo, RpcError> {
+    let mut blocks = get_block_infos(rpc, &[height]).await?;
+    Ok(blocks.remove(0))
+}
+
+/// Fetches many blocks in two round-trips: one batch for the hashes, one for blocks and stats.
+pub async fn get_block_infos(rpc: &RpcClient, heights: &[i64]) -> Result<Vec<BlockInfo>, RpcError> {
+    let hash_calls: Vec<(&str, Vec<Value>)> = heights
+        .iter()
+        .map(|&height| ("getblockhash", vec![json!(height)]))
+        .collect();
+    let hashes: Vec<String> = rpc.batch(&hash_calls).await?;
+
+    let detail_calls: Vec<(&str, Vec<Value>)> = heights
+        .iter()
+        .zip(&hashes)
+        .flat_map(|(&height, hash)| {
+            [
+                ("getblock", get_block_params(hash)),
+                ("getblockstats", get_block_stats_params(json!(height))),
+            ]
+        })
+        .collect();
+    let details: Vec<Value> = rpc.batch(&detail_calls).await?;
+
+    details
+        .chunks_exact(2)
+        .map(|pair| {
+            let block = VerboseBlock::deserialize(&pair[0])?;
+            let stats = BlockStats::deserialize(&pair[1])?;
+            Ok(block.into_info(stats.into()))
+        })
+        .collect()
+}
+
+pub async fn get_block_info_by_hash(rpc: &RpcClient, hash: &str) -> Result<BlockInfo, RpcError> {
+    let calls = [
+        ("getblock", get_block_params(hash)),
+        ("getblockstats", get_block_stats_params(json!(hash))),
+    ];
+    let details: Vec<Value> = rpc.batch(&calls).await?;
+    let block = VerboseBlock::deserialize(&details[0])?;
+    let stats = BlockStats::deserialize(&details[1])?;
+    Ok(block.into_info(stats.into()))
+}
+
+/// Verbose `getrawtransaction` output; the transaction itself is decoded from `hex`.
+#[derive(Deserialize)]
+struct RawTransactionVerbose {
+    hex: String,
+    blockhash: Option<String>,
+    confirmations: Option<i64>,
+    blocktime: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct BlockHeaderHeight {
+    height: i64,
+}
+
+/// Requires `txindex=1` on the node for transactions that are neither in the mempool nor
+/// in a wallet.
+pub async fn get_transaction(
+    rpc: &RpcClient,
+    txid: &str,
+    network: Network,
+) -> Result<TransactionDetail, RpcError> {
+    let verbose: RawTransactionVerbose = rpc
+        .call("getrawtransaction", &[json!(txid), json!(true)])
+        .await?;
+    let tx: RpcTransaction = deserialize_hex(&verbose.hex)?;
+    let prevouts = get_prevouts(rpc, &tx).await?;
+
+    let block_height = match &verbose.blockhash {
+        Some(hash) => {
+            let header: BlockHeaderHeight = rpc.call("getblockheader", &[json!(hash)]).await?;
+            Some(header.height)
+        }
+        None => None,
+    };
+    let status = TxStatus {
+        confirmed: verbose.blockhash.is_some(),
+        block_height,
+        block_hash: verbose.blockhash,
+        block_time: verbose.blocktime,
+        confirmations: verbose.confirmations,
+    };
+    Ok(TransactionDetail::decode(&tx, &prevouts, network, status))
+}
+
+/// Looks up the output spent by each input with one batched request for all parent transactions.
+/// Parents the node cannot return, e.g. confirmed ones without `txindex`, leave their prevouts empty.
+async fn get_prevouts(rpc: &RpcClient, tx: &RpcTransaction) -> Result<Vec<Option<TxOut>>, RpcError> {
+    if tx.is_coinbase() {
+        return Ok(vec![None; tx.input.len()]);
+    }
+
+    let mut parent_txids: Vec<Txid> = tx.input.iter().map(|input| input.previous_output.txid).collect();
+    parent_txids.sort();
+    parent_txids.dedup();
+    let calls: Vec<(&str, Vec<Value>)> = parent_txids
+        .iter()
+        .map(|txid| ("getrawtransaction", vec![json!(txid.to_string()), json!(false)]))
+        .collect();
+    let raw_parents: Vec<Result<String, RpcError>> = rpc.batch_results(&calls).await?;
+
+    let mut parents = HashMap::with_capacity(raw_parents.len());
+    for (txid, raw) in parent_txids.into_iter().zip(raw_parents) {
+        let parent = raw.and_then(|raw| Ok(deserialize_hex::<RpcTransaction>(&raw)?));
+        match parent {
+            Ok(parent) => {
+                parents.insert(txid, parent);
+            }
+            Err(e) => debug!("Prevouts of parent transaction {} unavailable: {}", txid, e),
+        }
+    }
+    Ok(tx
+        .input
+        .iter()
+        .map(|input| {
+            let outpoint = input.previous_output;
+            parents
+                .get(&outpoint.txid)
+                .and_then(|parent| parent.output.get(outpoint.vout as usize))
+                .cloned()
+        })
+        .collect())
+}
+
+/// Maps bitcoind's "not found" errors to `NotFoundError` so callers can tell them apart.
+fn into_provider_error(e: RpcError, what: impl FnOnce() -> String) -> ProviderError {
+    match e {
+        RpcError::Rpc { code, .. }
+            if code == RPC_INVALID_ADDRESS_OR_KEY || code == RPC_INVALID_PARAMETER =>
+        {
+            Box::new(NotFoundError(what()))
+        }
+        other => Box::new(other),
+    }
+}
+
+/// `ChainSource` backed by a local bitcoind node over JSON-RPC.
+pub struct BitcoinRpc {
+    rpc: RpcClient,
+    /// Used to render output addresses
+    network: Network,
+    /// Set once bitcoind has been seen on `network`
+    chain_checked: OnceCell<()>,
+}
+
+impl BitcoinRpc {
+    pub fn new(rpc: RpcClient, network: Network) -> Self {
+        BitcoinRpc {
+            rpc,
+            network,
+            chain_checked: OnceCell::new(),
+        }
+    }
+
+    /// Checks the chain bitcoind is on until that succeeds once.
+    async fn ensure_chain(&self) -> Result<(), RpcError> {
+        self.chain_checked
+            .get_or_try_init(|| check_chain(&self.rpc, self.network))
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ChainSource for BitcoinRpc {
+    fn backend(&self) -> &'static str {
+        "rpc"
+    }
+
+    fn network(&self) -> Network {
+        self.network
+    }
+
+    async fn get_block_count(&self) -> ProviderResult<i64> {
+        // Ingestion starts from the block count, so nothing is stored from the wrong chain
+        self.ensure_chain().await?;
+        Ok(get_block_count(&self.rpc).await?)
+    }
+
+    async fn get_block_hash(&self, height: i64) -> ProviderResult<String> {
+        get_block_hash(&self.rpc, height)
+            .await
+            .map_err(|e| into_provider_error(e, || format!("Block at height {}", height)))
+    }
+
+    async fn get_block_info(&self, height: i64) -> ProviderResult<BlockInfo> {
+        get_block_info(&self.rpc, height)
+            .await
+            .map_err(|e| into_provider_error(e, || format!("Block at height {}", height)))
+    }
+
+    async fn get_block_info_by_hash(&self, hash: &str) -> ProviderResult<BlockInfo> {
+        get_block_info_by_hash(&self.rpc, hash)
+            .await
+            .map_err(|e| into_provider_error(e, || format!("Block {}", hash)))
+    }
+
+    async fn get_block_infos(&self, heights: &[i64]) -> ProviderResult<Vec<BlockInfo>> {
+        Ok(get_block_infos(&self.rpc, heights).await?)
+    }
+
+    async fn get_difficulty(&self) -> ProviderResult<f64> {
+        Ok(get_difficulty(&self.rpc).await?)
+    }
+
+    async fn get_connection_count(&self) -> ProviderResult<u64> {
+        Ok(get_connection_count(&self.rpc).await?)
+    }
+
+    async fn get_mempool_info(&self, with_entries: bool) -> ProviderResult<MempoolInfo> {
+        Ok(get_mempool_info(&self.rpc, with_entries).await?)
+    }
+
+    async fn get_raw_block(&self, hash: &str) -> ProviderResult<String> {
+        let block_hash = BlockHash::from_str(hash)?;
+        self.rpc
+            .call("getblock", &[json!(block_hash.to_string()), json!(0)])
+            .await
+            .map_err(|e| into_provider_error(e, || format!("Block {}", hash)))
+    }
+
+    async fn get_raw_transaction(&self, txid: &str) -> ProviderResult<String> {
+        let parsed_txid = Txid::from_str(txid)?;
+        self.rpc
+            .call("getrawtransaction", &[json!(parsed_txid.to_string()), json!(false)])
+            .await
+            .map_err(|e| into_provider_error(e, || format!("Transaction {}", txid)))
+    }
+
+    async fn get_transaction(&self, txid: &str) -> ProviderResult<TransactionDetail> {
+        let parsed_txid = Txid::from_str(txid)?;
+        get_transaction(&self.rpc, &parsed_txid.to_string(), self.network)
+            .await
+            .map_err(|e| into_provider_error(e, || format!("Transaction {}", txid)))
+    }
+}
@@ -1,8 +1,19 @@
+use bitcoincore_rpc::bitcoin::Network;
 use std::env;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
 
 pub struct Config {
     pub use_api: bool,
     pub db_config: DatabaseConfig,
+    pub rpc_config: RpcConfig,
+    pub zmq_config: ZmqConfig,
+    pub backfill_config: BackfillConfig,
+    pub health_config: HealthConfig,
+    pub mempool_sample_interval_secs: u64,
+    /// How often a sample also lists every mempool entry for the feerate histogram
+    pub mempool_histogram_interval_secs: u64,
 }
 
 pub struct DatabaseConfig {
@@ -11,14 +22,86 @@ pub struct DatabaseConfig {
     pub username: String,
     pub password: String,
     pub database_name: String,
+    /// Apply pending migrations at startup instead of refusing to start
+    pub auto_migrate: bool,
+    /// Most connections the pool keeps open
+    pub pool_size: usize,
+    /// Longest to wait for a free connection or for a new one to open
+    pub pool_timeout: Duration,
+    /// Server-side limit for a single statement
+    pub statement_timeout: Duration,
+}
+
+pub struct RpcConfig {
+    pub url: String,
+    pub auth: RpcAuth,
+    pub timeout: Duration,
+    /// Chain the node is expected to be on; checked when connecting
+    pub network: Network,
+}
+
+#[derive(Clone)]
+pub enum RpcAuth {
+    UserPass { user: String, password: String },
+    /// Path to bitcoind's `.cookie` file, re-read on every request so node restarts are picked up
+    CookieFile(PathBuf),
+}
+
+/// bitcoind ZMQ endpoints, only used in RPC mode. Polling continues alongside them.
+pub struct ZmqConfig {
+    /// Endpoint of `zmqpubhashblock`
+    pub hashblock: Option<String>,
+    /// Endpoint of `zmqpubrawtx`
+    pub rawtx: Option<String>,
+    /// Endpoint of `zmqpubsequence`, used to tell mempool transactions from block transactions
+    pub sequence: Option<String>,
+    /// How long a socket may stay silent before it is reconnected
+    pub quiet_timeout: Duration,
+}
+
+pub struct BackfillConfig {
+    pub enabled: bool,
+    pub start_height: i64,
+    pub concurrency: usize,
+}
+
+/// Thresholds for `/readyz`.
+#[derive(Clone)]
+pub struct HealthConfig {
+    /// Blocks ingestion may trail the chain tip before the service reports itself degraded
+    pub max_ingestion_lag_blocks: i64,
+    /// How long a known chain tip stands in for asking the provider again
+    pub provider_check_ttl_secs: u64,
 }
 
 impl Config {
     pub fn from_env() -> Self {
         let use_api = env::var("USE_API").unwrap_or_else(|_| "false".to_string()) == "true";
+        let network = parse_network(&env::var("BITCOIN_NETWORK").unwrap_or_else(|_| "mainnet".to_string()));
         let db_config = DatabaseConfig::from_env();
+        let rpc_config = RpcConfig::from_env(network);
+        let zmq_config = ZmqConfig::from_env();
+        let backfill_config = BackfillConfig::from_env();
+        let health_config = HealthConfig::from_env();
+        let mempool_sample_interval_secs = env::var("MEMPOOL_SAMPLE_INTERVAL_SECS")
+            .unwrap_or_else(|_| "60".to_string())
+            .parse()
+            .expect("MEMPOOL_SAMPLE_INTERVAL_SECS must be a number of seconds");
+        let mempool_histogram_interval_secs = env::var("MEMPOOL_HISTOGRAM_INTERVAL_SECS")
+            .unwrap_or_else(|_| "600".to_string())
+            .parse()
+            .expect("MEMPOOL_HISTOGRAM_INTERVAL_SECS must be a number of seconds");
 
-        Config { use_api, db_config }
+        Config {
+            use_api,
+            db_config,
+            rpc_config,
+            zmq_config,
+            backfill_config,
+            health_config,
+            mempool_sample_interval_secs,
+            mempool_histogram_interval_secs,
+        }
     }
 }
 
@@ -33,6 +116,133 @@ impl DatabaseConfig {
             username: env::var("DB_USERNAME").expect("DB_USER must be set"),
             password: env::var("DB_PASSWORD").expect("DB_PASSWORD must be set"),
             database_name: env::var("DB_NAME").expect("DB_NAME must be set"),
+            auto_migrate: env::var("DB_AUTO_MIGRATE").unwrap_or_else(|_| "true".to_string()) == "true",
+            pool_size: env::var("DB_POOL_SIZE")
+                .unwrap_or_else(|_| "16".to_string())
+                .parse()
+                .expect("DB_POOL_SIZE must be a positive number"),
+            pool_timeout: Duration::from_secs(
+                env::var("DB_POOL_TIMEOUT_SECS")
+                    .unwrap_or_else(|_| "10".to_string())
+                    .parse()
+                    .expect("DB_POOL_TIMEOUT_SECS must be a number of seconds"),
+            ),
+            statement_timeout: Duration::from_secs(
+                env::var("DB_STATEMENT_TIMEOUT_SECS")
+                    .unwrap_or_else(|_| "30".to_string())
+                    .parse()
+                    .expect("DB_STATEMENT_TIMEOUT_SECS must be a number of seconds"),
+            ),
+        }
+    }
+}
+
+impl RpcConfig {
+    pub fn from_env(network: Network) -> Self {
+        let url = env::var("BITCOIN_RPC_URL")
+            .unwrap_or_else(|_| format!("http://127.0.0.1:{}", default_rpc_port(network)));
+        let auth = match (env::var("BITCOIN_RPC_USER"), env::var("BITCOIN_RPC_COOKIE_FILE")) {
+            (Ok(user), _) => RpcAuth::UserPass {
+                user,
+                password: env::var("BITCOIN_RPC_PASSWORD").expect("BITCOIN_RPC_PASSWORD must be set"),
+            },
+            (Err(_), Ok(path)) => RpcAuth::CookieFile(PathBuf::from(path)),
+            // Same place bitcoin-cli looks when no credentials are given
+            (Err(_), Err(_)) => RpcAuth::CookieFile(default_cookie_path(network)),
+        };
+        let timeout = env::var("BITCOIN_RPC_TIMEOUT_SECS")
+            .unwrap_or_else(|_| "30".to_string())
+            .parse()
+            .expect("BITCOIN_RPC_TIMEOUT_SECS must be a number of seconds");
+
+        RpcConfig {
+            url,
+            auth,
+            timeout: Duration::from_secs(timeout),
+            network,
+        }
+    }
+}
+
+/// Accepts bitcoind's chain names as well as "mainnet"/"testnet3" aliases.
+fn parse_network(name: &str) -> Network {
+    match name {
+        "mainnet" | "main" => Network::Bitcoin,
+        "testnet3" | "test" => Network::Testnet,
+        other => Network::from_str(other)
+            .expect("BITCOIN_NETWORK must be one of mainnet, testnet, testnet4, signet or regtest"),
+    }
+}
+
+fn default_cookie_path(network: Network) -> PathBuf {
+    let mut path = PathBuf::from(env::var("HOME").unwrap_or_default()).join(".bitcoin");
+    match network {
+        Network::Testnet => path.push("testnet3"),
+        Network::Testnet4 => path.push("testnet4"),
+        Network::Signet => path.push("signet"),
+        Network::Regtest => path.push("regtest"),
+        _ => {}
+    }
+    path.join(".cookie")
+}
+
+fn default_rpc_port(network: Network) -> u16 {
+    match network {
+        Network::Testnet => 18332,
+        Network::Testnet4 => 48332,
+        Network::Signet => 38332,
+        Network::Regtest => 18443,
+        _ => 8332,
+    }
+}
+
+impl ZmqConfig {
+    pub fn from_env() -> Self {
+        let quiet_timeout = env::var("BITCOIN_ZMQ_QUIET_TIMEOUT_SECS")
+            .unwrap_or_else(|_| "1800".to_string())
+            .parse()
+            .expect("BITCOIN_ZMQ_QUIET_TIMEOUT_SECS must be a number of seconds");
+
+        ZmqConfig {
+            hashblock: env::var("BITCOIN_ZMQ_HASHBLOCK").ok(),
+            rawtx: env::var("BITCOIN_ZMQ_RAWTX").ok(),
+            sequence: env::var("BITCOIN_ZMQ_SEQUENCE").ok(),
+            quiet_timeout: Duration::from_secs(quiet_timeout),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.hashblock.is_some() || self.rawtx.is_some()
+    }
+}
+
+impl BackfillConfig {
+    pub fn from_env() -> Self {
+        BackfillConfig {
+            enabled: env::var("BACKFILL").unwrap_or_else(|_| "false".to_string()) == "true",
+            start_height: env::var("BACKFILL_START_HEIGHT")
+                .unwrap_or_else(|_| "0".to_string())
+                .parse()
+                .expect("BACKFILL_START_HEIGHT must be a valid block height"),
+            concurrency: env::var("BACKFILL_CONCURRENCY")
+                .unwrap_or_else(|_| "4".to_string())
+                .parse()
+                .expect("BACKFILL_CONCURRENCY must be a positive number"),
+        }
+    }
+}
+
+impl HealthConfig {
+    pub fn from_env() -> Self {
+        HealthConfig {
+            max_ingestion_lag_blocks: env::var("READY_MAX_INGESTION_LAG_BLOCKS")
+                .unwrap_or_else(|_| "3".to_string())
+                .parse()
+                .expect("READY_MAX_INGESTION_LAG_BLOCKS must be a number of blocks"),
+            provider_check_ttl_secs: env::var("READY_PROVIDER_CHECK_TTL_SECS")
+                .unwrap_or_else(|_| "10".to_string())
+                .parse()
+                .expect("READY_PROVIDER_CHECK_TTL_SECS must be a number of seconds"),
         }
     }
 }
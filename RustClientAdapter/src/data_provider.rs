@@ -1,82 +1,443 @@
 use crate::bitcoin_api::BitcoinApi;
-use crate::bitcoin_rpc::{
-    connect_to_bitcoin_rpc, get_block_count, get_block_info, get_connection_count, get_difficulty,
-    RpcClient,
-};
+use crate::bitcoin_rpc::{connect_to_bitcoin_rpc, RpcTransaction};
+use crate::config::RpcConfig;
+use crate::telemetry;
+use async_trait::async_trait;
+use bitcoincore_rpc::bitcoin::{Address, Network, Script, TxOut};
+use serde::Serialize;
+use std::future::Future;
+use std::time::Instant;
+use thiserror::Error;
 
-pub enum DataSource {
-    RPC(RpcClient),
-    API(BitcoinApi),
-}
+pub type ProviderError = Box<dyn std::error::Error + Send + Sync>;
+pub type ProviderResult<T> = Result<T, ProviderError>;
+
+/// Returned by backends when the requested block or transaction does not exist.
+#[derive(Error, Debug)]
+#[error("{0} not found")]
+pub struct NotFoundError(pub String);
 
 pub struct BlockInfo {
     pub height: i64,
     pub hash: String,
+    pub prev_hash: String,
+    pub version: i32,
+    pub merkle_root: String,
+    pub nonce: u32,
+    /// Compact encoding of the target
+    pub bits: u32,
     pub timestamp: i64,
     pub tx_count: u32,
     pub size: u32,
+    /// Per-transaction details, only populated by backends that fetch full blocks
+    pub transactions: Vec<TransactionInfo>,
+    pub fee_stats: BlockFeeStats,
+}
+
+/// Fee economics of a single block. Feerates are in sat/vB and exclude the coinbase.
+pub struct BlockFeeStats {
+    pub total_fee: i64,
+    pub subsidy: i64,
+    pub min_feerate: f64,
+    pub max_feerate: f64,
+    /// Feerates at the 10th, 25th, 50th, 75th and 90th percentile, weighted by size
+    pub feerate_percentiles: [f64; 5],
+}
+
+pub struct TransactionInfo {
+    pub txid: String,
+    pub position: u32,
+    pub vsize: u32,
+    pub weight: u32,
+    /// Fee in satoshis, `None` for the coinbase or when the backend cannot determine it
+    pub fee: Option<i64>,
+    pub input_count: u32,
+    pub output_count: u32,
+    pub is_segwit: bool,
+    pub is_taproot: bool,
+    /// Spent outputs, excluding the coinbase input
+    pub inputs: Vec<InputInfo>,
+    /// Inputs left out of `inputs` because the backend did not return the output they spend
+    pub missing_prevouts: u32,
+    pub outputs: Vec<OutputInfo>,
+}
+
+pub struct InputInfo {
+    /// Position among the transaction's inputs
+    pub vin: u32,
+    pub prev_txid: String,
+    pub prev_vout: u32,
+    /// Value of the spent output in satoshis
+    pub value: i64,
+    pub address: Option<String>,
+}
+
+pub struct OutputInfo {
+    /// Value in satoshis
+    pub value: i64,
+    /// `None` for scripts without an address form, e.g. `OP_RETURN`
+    pub address: Option<String>,
+}
+
+/// A fully decoded transaction as served by `/tx/{txid}`.
+#[derive(Serialize)]
+pub struct TransactionDetail {
+    pub txid: String,
+    pub version: i32,
+    pub locktime: u32,
+    pub size: u32,
+    pub vsize: u32,
+    pub weight: u32,
+    /// Fee in satoshis, `None` for the coinbase or when a prevout is unknown
+    pub fee: Option<i64>,
+    /// Fee in sat/vB
+    pub feerate: Option<f64>,
+    /// Signals opt-in replace-by-fee (BIP 125)
+    pub rbf: bool,
+    pub has_witness: bool,
+    pub inputs: Vec<TxInputDetail>,
+    pub outputs: Vec<TxOutputDetail>,
+    pub status: TxStatus,
+}
+
+#[derive(Serialize)]
+pub struct TxInputDetail {
+    /// `None` for the coinbase input
+    pub prev_txid: Option<String>,
+    pub prev_vout: Option<u32>,
+    pub sequence: u32,
+    pub witness_items: u32,
+    pub prevout: Option<TxOutputDetail>,
+}
+
+#[derive(Serialize)]
+pub struct TxOutputDetail {
+    /// Value in satoshis
+    pub value: i64,
+    /// Script type using bitcoind's names, e.g. `witness_v0_keyhash`
+    pub script_type: &'static str,
+    pub address: Option<String>,
+}
+
+/// Where a transaction sits on the chain. Backends fill in as much as they know.
+#[derive(Serialize, Default)]
+pub struct TxStatus {
+    pub confirmed: bool,
+    pub block_height: Option<i64>,
+    pub block_hash: Option<String>,
+    pub block_time: Option<i64>,
+    pub confirmations: Option<i64>,
+}
+
+impl TransactionDetail {
+    /// Decodes `tx`; `prevouts` holds the output spent by each input, `None` for the coinbase.
+    pub fn decode(
+        tx: &RpcTransaction,
+        prevouts: &[Option<TxOut>],
+        network: Network,
+        status: TxStatus,
+    ) -> Self {
+        let is_coinbase = tx.is_coinbase();
+        let inputs: Vec<TxInputDetail> = tx
+            .input
+            .iter()
+            .zip(prevouts)
+            .map(|(input, prevout)| TxInputDetail {
+                prev_txid: (!is_coinbase).then(|| input.previous_output.txid.to_string()),
+                prev_vout: (!is_coinbase).then_some(input.previous_output.vout),
+                sequence: input.sequence.0,
+                witness_items: input.witness.len() as u32,
+                prevout: prevout.as_ref().map(|out| describe_output(out, network)),
+            })
+            .collect();
+        let outputs: Vec<TxOutputDetail> = tx
+            .output
+            .iter()
+            .map(|out| describe_output(out, network))
+            .collect();
+
+        let vsize = tx.vsize() as u32;
+        let fee = if is_coinbase || inputs.iter().any(|input| input.prevout.is_none()) {
+            None
+        } else {
+            let spent: i64 = inputs.iter().flat_map(|input| &input.prevout).map(|out| out.value).sum();
+            let created: i64 = outputs.iter().map(|out| out.value).sum();
+            Some(spent - created)
+        };
+
+        TransactionDetail {
+            txid: tx.compute_txid().to_string(),
+            version: tx.version.0,
+            locktime: tx.lock_time.to_consensus_u32(),
+            size: tx.total_size() as u32,
+            vsize,
+            weight: tx.weight().to_wu() as u32,
+            fee,
+            feerate: fee.filter(|_| vsize > 0).map(|fee| fee as f64 / f64::from(vsize)),
+            rbf: tx.is_explicitly_rbf(),
+            has_witness: tx.input.iter().any(|input| !input.witness.is_empty()),
+            inputs,
+            outputs,
+            status,
+        }
+    }
+}
+
+fn describe_output(out: &TxOut, network: Network) -> TxOutputDetail {
+    TxOutputDetail {
+        value: out.value.to_sat() as i64,
+        script_type: script_type(&out.script_pubkey),
+        address: Address::from_script(&out.script_pubkey, network)
+            .ok()
+            .map(|address| address.to_string()),
+    }
+}
+
+fn script_type(script: &Script) -> &'static str {
+    if script.is_p2pkh() {
+        "pubkeyhash"
+    } else if script.is_p2sh() {
+        "scripthash"
+    } else if script.is_p2wpkh() {
+        "witness_v0_keyhash"
+    } else if script.is_p2wsh() {
+        "witness_v0_scripthash"
+    } else if script.is_p2tr() {
+        "witness_v1_taproot"
+    } else if script.is_op_return() {
+        "nulldata"
+    } else if script.is_p2pk() {
+        "pubkey"
+    } else if script.is_multisig() {
+        "multisig"
+    } else if script.is_witness_program() {
+        "witness_unknown"
+    } else {
+        "nonstandard"
+    }
+}
+
+/// Balance and activity of a single address as served by `/address/{addr}`. Amounts are in satoshis.
+#[derive(Serialize)]
+pub struct AddressInfo {
+    pub address: String,
+    pub confirmed_balance: i64,
+    /// Net change from unconfirmed transactions, `None` when the backend cannot tell
+    pub unconfirmed_balance: Option<i64>,
+    pub total_received: i64,
+    pub total_sent: i64,
+    pub tx_count: i64,
+    /// One page of history, newest first
+    pub transactions: Vec<AddressTransaction>,
+    pub utxos: Vec<AddressUtxo>,
+    /// Offset of the next history page, if there is one
+    pub next_offset: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct AddressTransaction {
+    pub txid: String,
+    /// `None` while unconfirmed
+    pub block_height: Option<i64>,
+    pub block_time: Option<i64>,
+    /// Net effect of the transaction on the address balance
+    pub value_change: i64,
+}
+
+#[derive(Serialize)]
+pub struct AddressUtxo {
+    pub txid: String,
+    pub vout: u32,
+    pub value: i64,
+    pub block_height: Option<i64>,
+}
+
+/// Aggregate view of the mempool plus the individual entries used to build feerate histograms.
+pub struct MempoolInfo {
+    pub tx_count: u64,
+    pub vsize: u64,
+    /// Total fees in satoshis
+    pub total_fee: i64,
+    /// Minimum relay feerate in sat/vB, if the backend reports one
+    pub min_relay_feerate: Option<f64>,
+    /// Individual transactions, absent when they were not requested or could not be listed
+    pub entries: Option<Vec<MempoolEntry>>,
+}
+
+pub struct MempoolEntry {
+    /// Fee in satoshis
+    pub fee: i64,
+    pub vsize: u32,
+}
+
+const INITIAL_SUBSIDY: i64 = 50 * 100_000_000;
+/// Blocks between subsidy halvings
+pub const HALVING_INTERVAL: i64 = 210_000;
+
+/// Block subsidy in satoshis at the given height.
+pub fn block_subsidy(height: i64) -> i64 {
+    let halvings = height / HALVING_INTERVAL;
+    if halvings >= 64 {
+        0
+    } else {
+        INITIAL_SUBSIDY >> halvings
+    }
+}
+
+/// A backend that can answer chain queries, e.g. a bitcoind node or a public block explorer API.
+#[async_trait]
+pub trait ChainSource: Send + Sync {
+    /// Name used to label this backend's request metrics.
+    fn backend(&self) -> &'static str;
+
+    /// The network whose address encoding this backend uses.
+    fn network(&self) -> Network;
+
+    async fn get_block_count(&self) -> ProviderResult<i64>;
+
+    async fn get_block_hash(&self, height: i64) -> ProviderResult<String>;
+
+    async fn get_block_info(&self, height: i64) -> ProviderResult<BlockInfo>;
+
+    async fn get_block_info_by_hash(&self, hash: &str) -> ProviderResult<BlockInfo>;
+
+    /// Fetches several blocks at once; backends that support batching should override this.
+    async fn get_block_infos(&self, heights: &[i64]) -> ProviderResult<Vec<BlockInfo>> {
+        let mut blocks = Vec::with_capacity(heights.len());
+        for &height in heights {
+            blocks.push(self.get_block_info(height).await?);
+        }
+        Ok(blocks)
+    }
+
+    async fn get_difficulty(&self) -> ProviderResult<f64>;
+
+    async fn get_connection_count(&self) -> ProviderResult<u64>;
+
+    /// Summarizes the mempool, listing its entries too when `with_entries` is set.
+    async fn get_mempool_info(&self, with_entries: bool) -> ProviderResult<MempoolInfo>;
+
+    /// Returns the hex-encoded serialized block with the given hash.
+    async fn get_raw_block(&self, hash: &str) -> ProviderResult<String>;
+
+    /// Returns the hex-encoded serialized transaction with the given txid.
+    async fn get_raw_transaction(&self, txid: &str) -> ProviderResult<String>;
+
+    /// Returns the decoded transaction with its prevouts and confirmation status.
+    async fn get_transaction(&self, txid: &str) -> ProviderResult<TransactionDetail>;
+
+    /// Returns balance, a page of history and the UTXOs of an address. Backends without an
+    /// address index return `None`, and callers fall back to the index built during ingestion.
+    async fn get_address_info(
+        &self,
+        _address: &str,
+        _limit: i64,
+        _offset: i64,
+    ) -> ProviderResult<Option<AddressInfo>> {
+        Ok(None)
+    }
 }
 
 pub struct BitcoinDataProvider {
-    source: DataSource,
+    source: Box<dyn ChainSource>,
 }
 
 impl BitcoinDataProvider {
-    pub fn new(use_api: bool) -> Self {
-        let source = if use_api {
-            DataSource::API(BitcoinApi::new())
+    pub async fn new(use_api: bool, rpc_config: &RpcConfig) -> ProviderResult<Self> {
+        let source: Box<dyn ChainSource> = if use_api {
+            if rpc_config.network != Network::Bitcoin {
+                return Err(format!(
+                    "USE_API=true only supports mainnet, but BITCOIN_NETWORK is {}",
+                    rpc_config.network
+                )
+                .into());
+            }
+            Box::new(BitcoinApi::new())
         } else {
-            DataSource::RPC(connect_to_bitcoin_rpc())
+            Box::new(connect_to_bitcoin_rpc(rpc_config).await?)
         };
+        Ok(Self::from_source(source))
+    }
+
+    pub fn from_source(source: Box<dyn ChainSource>) -> Self {
         BitcoinDataProvider { source }
     }
 
-    pub async fn get_block_count(&self) -> Result<i64, Box<dyn std::error::Error>> {
-        match &self.source {
-            DataSource::RPC(rpc) => Ok(i64::from(get_block_count(rpc)?)),
-            DataSource::API(api) => api.get_block_count().await,
-        }
+    pub fn network(&self) -> Network {
+        self.source.network()
     }
 
-    pub async fn get_difficulty(&self) -> Result<f64, Box<dyn std::error::Error>> {
-        match &self.source {
-            DataSource::RPC(rpc) => Ok(get_difficulty(rpc)?),
-            DataSource::API(api) => api.get_difficulty().await,
-        }
+    pub fn backend(&self) -> &'static str {
+        self.source.backend()
     }
 
-    pub async fn get_connection_count(&self) -> Result<u64, Box<dyn std::error::Error>> {
-        match &self.source {
-            DataSource::RPC(rpc) => Ok(get_connection_count(rpc)?),
-            DataSource::API(_) => Ok(0), // API doesn't provide connection count, return 0
-        }
+    /// Awaits a backend request and records its outcome and latency.
+    async fn observe<T>(
+        &self,
+        method: &'static str,
+        request: impl Future<Output = ProviderResult<T>>,
+    ) -> ProviderResult<T> {
+        let started = Instant::now();
+        let result = request.await;
+        telemetry::record_provider_request(
+            self.source.backend(),
+            method,
+            result.is_ok(),
+            started.elapsed(),
+        );
+        result
     }
 
-    pub async fn get_block_info(
+    pub async fn get_block_count(&self) -> ProviderResult<i64> {
+        self.observe("get_block_count", self.source.get_block_count()).await
+    }
+
+    pub async fn get_difficulty(&self) -> ProviderResult<f64> {
+        self.observe("get_difficulty", self.source.get_difficulty()).await
+    }
+
+    pub async fn get_connection_count(&self) -> ProviderResult<u64> {
+        self.observe("get_connection_count", self.source.get_connection_count()).await
+    }
+
+    pub async fn get_block_hash(&self, height: i64) -> ProviderResult<String> {
+        self.observe("get_block_hash", self.source.get_block_hash(height)).await
+    }
+
+    pub async fn get_block_info(&self, height: i64) -> ProviderResult<BlockInfo> {
+        self.observe("get_block_info", self.source.get_block_info(height)).await
+    }
+
+    pub async fn get_block_info_by_hash(&self, hash: &str) -> ProviderResult<BlockInfo> {
+        self.observe("get_block_info_by_hash", self.source.get_block_info_by_hash(hash)).await
+    }
+
+    pub async fn get_block_infos(&self, heights: &[i64]) -> ProviderResult<Vec<BlockInfo>> {
+        self.observe("get_block_infos", self.source.get_block_infos(heights)).await
+    }
+
+    pub async fn get_mempool_info(&self, with_entries: bool) -> ProviderResult<MempoolInfo> {
+        self.observe("get_mempool_info", self.source.get_mempool_info(with_entries)).await
+    }
+
+    pub async fn get_raw_block(&self, hash: &str) -> ProviderResult<String> {
+        self.observe("get_raw_block", self.source.get_raw_block(hash)).await
+    }
+
+    pub async fn get_raw_transaction(&self, txid: &str) -> ProviderResult<String> {
+        self.observe("get_raw_transaction", self.source.get_raw_transaction(txid)).await
+    }
+
+    pub async fn get_transaction(&self, txid: &str) -> ProviderResult<TransactionDetail> {
+        self.observe("get_transaction", self.source.get_transaction(txid)).await
+    }
+
+    pub async fn get_address_info(
         &self,
-        height: i64,
-    ) -> Result<BlockInfo, Box<dyn std::error::Error>> {
-        match &self.source {
-            DataSource::RPC(rpc) => {
-                let info = get_block_info(rpc, height)?;
-                Ok(BlockInfo {
-                    height,
-                    hash: info.hash,
-                    timestamp: info.time,
-                    tx_count: info.n_tx,
-                    size: info.size,
-                })
-            }
-            DataSource::API(api) => {
-                let api_block_info = api.get_block_info(height).await?;
-                Ok(BlockInfo {
-                    height,
-                    hash: api_block_info.hash,
-                    timestamp: api_block_info.time,
-                    tx_count: api_block_info.n_tx,
-                    size: api_block_info.size,
-                })
-            }
-        }
+        address: &str,
+        limit: i64,
+        offset: i64,
+    ) -> ProviderResult<Option<AddressInfo>> {
+        self.observe("get_address_info", self.source.get_address_info(address, limit, offset)).await
     }
 }
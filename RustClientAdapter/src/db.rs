@@ -1,30 +1,50 @@
 use crate::config::DatabaseConfig;
+use crate::migrations::{run_migrations, verify_migrations, MigrationState};
+use deadpool_postgres::{
+    BuildError, Manager, ManagerConfig, Object, Pool, PoolError, RecyclingMethod, Runtime,
+};
 use std::time::Duration;
 use tokio::time::sleep;
-use tokio_postgres::{Client, NoTls};
+use tokio_postgres::NoTls;
 
-pub async fn connect_to_postgres(config: &DatabaseConfig) -> Result<Client, tokio_postgres::Error> {
-    let connection_string = format!(
-        "host={} port={} user={} password={} dbname={}",
-        config.host, config.port, config.username, config.password, config.database_name
+/// Builds the connection pool. Connections are opened on demand, checked with a round trip
+/// before being handed out again, and replaced when they were closed, so a dropped connection
+/// only fails the queries that were running on it.
+pub fn create_pool(config: &DatabaseConfig) -> Result<Pool, BuildError> {
+    let mut pg_config = tokio_postgres::Config::new();
+    pg_config
+        .host(&config.host)
+        .port(config.port)
+        .user(&config.username)
+        .password(&config.password)
+        .dbname(&config.database_name)
+        .connect_timeout(config.pool_timeout)
+        .options(format!(
+            "-c statement_timeout={}",
+            config.statement_timeout.as_millis()
+        ));
+    let manager = Manager::from_config(
+        pg_config,
+        NoTls,
+        ManagerConfig {
+            recycling_method: RecyclingMethod::Verified,
+        },
     );
-    let (client, connection) = tokio_postgres::connect(&connection_string, NoTls).await?;
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            eprintln!("Connection error: {}", e);
-        }
-    });
-    Ok(client)
+    Pool::builder(manager)
+        .max_size(config.pool_size)
+        .runtime(Runtime::Tokio1)
+        .wait_timeout(Some(config.pool_timeout))
+        .create_timeout(Some(config.pool_timeout))
+        .build()
 }
 
-pub async fn connect_to_postgres_with_retry(
-    config: &DatabaseConfig,
-) -> Result<Client, tokio_postgres::Error> {
+/// Waits for the database to accept connections, for use at startup.
+pub async fn get_connection_with_retry(pool: &Pool) -> Result<Object, PoolError> {
     let mut retries = 5;
     let mut delay = Duration::from_secs(1);
 
     loop {
-        match connect_to_postgres(config).await {
+        match pool.get().await {
             Ok(client) => return Ok(client),
             Err(e) => {
                 if retries == 0 {
@@ -46,86 +66,31 @@ pub async fn connect_to_postgres_with_retry(
     }
 }
 
-pub async fn initialize_database(client: &Client) -> Result<(), tokio_postgres::Error> {
-    const CREATE_TABLE_SQL: &str = "
-    CREATE TABLE IF NOT EXISTS blockchain_metrics (
-        id SERIAL PRIMARY KEY,
-        timestamp TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
-        block_height BIGINT NOT NULL,
-        difficulty TEXT NOT NULL,
-        connection_count INTEGER,
-        tx_count INTEGER,
-        block_size INTEGER,
-        block_timestamp BIGINT,
-        block_hash TEXT
-    );
-
-    DO $$ 
-    BEGIN
-        -- Add a unique constraint on block_height if it doesn't exist
-        IF NOT EXISTS (
-            SELECT 1 FROM pg_constraint 
-            WHERE conname = 'unique_block_height' AND conrelid = 'blockchain_metrics'::regclass
-        ) THEN
-            ALTER TABLE blockchain_metrics ADD CONSTRAINT unique_block_height UNIQUE (block_height);
-        END IF;
-
-        -- Create indexes for faster queries
-        CREATE INDEX IF NOT EXISTS idx_blockchain_metrics_timestamp ON blockchain_metrics (timestamp);
-        CREATE INDEX IF NOT EXISTS idx_blockchain_metrics_block_height ON blockchain_metrics (block_height);
-    END $$;
-
-    -- Create a function to update the timestamp
-    CREATE OR REPLACE FUNCTION update_timestamp()
-    RETURNS TRIGGER AS $$
-    BEGIN
-        NEW.timestamp = CURRENT_TIMESTAMP;
-        RETURN NEW;
-    END;
-    $$ LANGUAGE plpgsql;
-
-    -- Create a trigger to automatically update the timestamp
-    DROP TRIGGER IF EXISTS update_blockchain_metrics_timestamp ON blockchain_metrics;
-    CREATE TRIGGER update_blockchain_metrics_timestamp
-    BEFORE UPDATE ON blockchain_metrics
-    FOR EACH ROW
-    EXECUTE FUNCTION update_timestamp();
-
-    -- Create a function for upserting blockchain metrics
-    CREATE OR REPLACE FUNCTION upsert_blockchain_metrics(
-        p_block_height BIGINT,
-        p_difficulty TEXT,
-        p_connection_count INTEGER,
-        p_tx_count INTEGER,
-        p_block_size INTEGER,
-        p_block_timestamp BIGINT,
-        p_block_hash TEXT
-    ) RETURNS VOID AS $$
-    BEGIN
-        INSERT INTO blockchain_metrics (
-            block_height, difficulty, connection_count, tx_count, block_size, block_timestamp, block_hash
-        ) VALUES (
-            p_block_height, p_difficulty, p_connection_count, p_tx_count, p_block_size, p_block_timestamp, p_block_hash
-        )
-        ON CONFLICT (block_height) DO UPDATE SET
-            difficulty = EXCLUDED.difficulty,
-            connection_count = EXCLUDED.connection_count,
-            tx_count = EXCLUDED.tx_count,
-            block_size = EXCLUDED.block_size,
-            block_timestamp = EXCLUDED.block_timestamp,
-            block_hash = EXCLUDED.block_hash,
-            timestamp = CURRENT_TIMESTAMP;
-    END;
-    $$ LANGUAGE plpgsql;
-    ";
-
-    // Execute all SQL statements
-    client.batch_execute(CREATE_TABLE_SQL).await?;
-    println!("Database initialized successfully");
-    Ok(())
-}
-pub async fn setup_database(config: &DatabaseConfig) -> Result<Client, Box<dyn std::error::Error>> {
-    let client = connect_to_postgres_with_retry(config).await?;
-    initialize_database(&client).await?;
-    Ok(client)
+/// Creates the pool and brings the schema up to date. With `DB_AUTO_MIGRATE=false` the schema
+/// is only checked, so migrations have to be applied with `rust_client_adapter migrate up`.
+pub async fn setup_database(config: &DatabaseConfig) -> Result<Pool, Box<dyn std::error::Error>> {
+    let pool = create_pool(config)?;
+    let mut client = get_connection_with_retry(&pool).await?;
+    if config.auto_migrate {
+        let applied = run_migrations(&mut client).await?;
+        println!(
+            "Database schema is up to date ({} migration(s) applied)",
+            applied.len()
+        );
+    } else {
+        let pending: Vec<i64> = verify_migrations(&client)
+            .await?
+            .iter()
+            .filter(|status| status.state == MigrationState::Pending)
+            .map(|status| status.version)
+            .collect();
+        if !pending.is_empty() {
+            return Err(format!(
+                "Database is missing migrations {:?}, run `rust_client_adapter migrate up`",
+                pending
+            )
+            .into());
+        }
+    }
+    Ok(pool)
 }
@@ -0,0 +1,154 @@
+use crate::telemetry;
+use bitcoincore_rpc::bitcoin::{CompactTarget, Target};
+use serde::Serialize;
+use tokio_postgres::Client;
+
+/// Blocks between difficulty adjustments
+pub const RETARGET_INTERVAL: i64 = 2016;
+/// Seconds per block the difficulty adjustment aims for
+pub const TARGET_BLOCK_SPACING: i64 = 600;
+/// Largest factor difficulty can move by in a single adjustment
+const MAX_ADJUSTMENT_FACTOR: f64 = 4.0;
+
+/// Difficulty of a block from the compact target in its header, relative to the mainnet
+/// maximum target like `getdifficulty`.
+pub fn difficulty_from_bits(bits: u32) -> f64 {
+    Target::from_compact(CompactTarget::from_consensus(bits)).difficulty_float()
+}
+
+/// Factor the next adjustment would multiply difficulty by if the rest of the epoch keeps
+/// `avg_block_interval`. Like bitcoind, the measured timespan covers one block less than the epoch.
+pub fn projected_adjustment(avg_block_interval: f64) -> f64 {
+    let expected = (RETARGET_INTERVAL * TARGET_BLOCK_SPACING) as f64;
+    let actual = avg_block_interval * (RETARGET_INTERVAL - 1) as f64;
+    (expected / actual).clamp(1.0 / MAX_ADJUSTMENT_FACTOR, MAX_ADJUSTMENT_FACTOR)
+}
+
+/// One retarget period, built from the blocks stored for it. Heights and times come from the
+/// first and last stored block, so they only match the epoch boundaries once it is fully ingested.
+#[derive(Serialize)]
+pub struct DifficultyEpoch {
+    pub epoch: i64,
+    pub start_height: i64,
+    /// Unix timestamp of the first block
+    pub start_time: Option<i64>,
+    pub difficulty: f64,
+    /// Change from the previous epoch, when that epoch is stored as well
+    pub change_percent: Option<f64>,
+    /// Mean seconds between the stored blocks of the epoch
+    pub avg_block_interval: Option<f64>,
+    /// Blocks of the epoch that are stored
+    pub block_count: i64,
+}
+
+/// Lists every epoch with stored blocks, newest first. Testnet allows minimum difficulty blocks
+/// inside an epoch, so the epoch difficulty is the highest one seen in it.
+pub async fn load_difficulty_epochs(
+    client: &Client,
+) -> Result<Vec<DifficultyEpoch>, tokio_postgres::Error> {
+    let rows = telemetry::observe_db(
+        "load_difficulty_epochs",
+        client.query(
+            "WITH epochs AS (
+                SELECT block_height / $1 AS epoch,
+                       MIN(block_height) AS start_height,
+                       MAX(block_height) AS end_height,
+                       COUNT(*) AS block_count,
+                       MAX(difficulty) AS difficulty,
+                       (ARRAY_AGG(block_timestamp ORDER BY block_height))[1] AS start_time,
+                       (ARRAY_AGG(block_timestamp ORDER BY block_height DESC))[1] AS end_time
+                FROM blockchain_metrics
+                GROUP BY 1
+             )
+             SELECT *,
+                    LAG(epoch) OVER w AS previous_epoch,
+                    LAG(difficulty) OVER w AS previous_difficulty
+             FROM epochs
+             WINDOW w AS (ORDER BY epoch)
+             ORDER BY epoch DESC",
+            &[&RETARGET_INTERVAL],
+        ),
+    )
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let epoch: i64 = row.get("epoch");
+            let start_height: i64 = row.get("start_height");
+            let end_height: i64 = row.get("end_height");
+            let start_time: Option<i64> = row.get("start_time");
+            let end_time: Option<i64> = row.get("end_time");
+            let difficulty: f64 = row.get("difficulty");
+            let previous_epoch: Option<i64> = row.get("previous_epoch");
+            let previous_difficulty: Option<f64> = row.get("previous_difficulty");
+
+            let change_percent = match (previous_epoch, previous_difficulty) {
+                (Some(previous), Some(previous_difficulty))
+                    if previous == epoch - 1 && previous_difficulty > 0.0 =>
+                {
+                    Some((difficulty / previous_difficulty - 1.0) * 100.0)
+                }
+                _ => None,
+            };
+            let avg_block_interval = match (start_time, end_time) {
+                (Some(start), Some(end)) if end_height > start_height => {
+                    Some((end - start) as f64 / (end_height - start_height) as f64)
+                }
+                _ => None,
+            };
+            DifficultyEpoch {
+                epoch,
+                start_height,
+                start_time,
+                difficulty,
+                change_percent,
+                avg_block_interval,
+                block_count: row.get("block_count"),
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            ((actual - expected) / expected).abs() < 1e-12,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn difficulty_of_the_maximum_target_is_one() {
+        assert_eq!(difficulty_from_bits(0x1d00ffff), 1.0);
+    }
+
+    #[test]
+    fn difficulty_matches_getdifficulty_for_mainnet_blocks() {
+        // Block 32256, the first retarget above difficulty 1
+        assert_close(difficulty_from_bits(0x1d00d86a), 1.182899534312841);
+        // Block 100000
+        assert_close(difficulty_from_bits(0x1b04864c), 14484.162361225399);
+        // Block 840000
+        assert_close(difficulty_from_bits(0x17034219), 86388558925171.02);
+    }
+
+    #[test]
+    fn projected_adjustment_is_clamped_to_a_factor_of_four() {
+        assert_eq!(projected_adjustment(1.0), MAX_ADJUSTMENT_FACTOR);
+        assert_eq!(projected_adjustment(100_000.0), 1.0 / MAX_ADJUSTMENT_FACTOR);
+        // Just inside the bounds the factor is not clamped
+        assert_close(projected_adjustment(151.0), 4.0 * 2016.0 / 2015.0 * 150.0 / 151.0);
+        assert_close(projected_adjustment(2401.0), 2016.0 / 2015.0 * 600.0 / 2401.0);
+    }
+
+    #[test]
+    fn projected_adjustment_measures_one_block_less_than_the_epoch() {
+        assert_close(projected_adjustment(600.0), 2016.0 / 2015.0);
+    }
+}
@@ -0,0 +1,282 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+
+/// How far a slow subscriber may fall behind before it starts missing events
+const CHANNEL_CAPACITY: usize = 256;
+/// Recent events kept for clients that reconnect with `Last-Event-ID`
+const HISTORY_SIZE: usize = 1024;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Topic {
+    NewBlock,
+    Reorg,
+    MempoolUpdate,
+    FeeEstimate,
+    MempoolTx,
+}
+
+impl Topic {
+    pub const ALL: [Topic; 5] = [
+        Topic::NewBlock,
+        Topic::Reorg,
+        Topic::MempoolUpdate,
+        Topic::FeeEstimate,
+        Topic::MempoolTx,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Topic::NewBlock => "new_block",
+            Topic::Reorg => "reorg",
+            Topic::MempoolUpdate => "mempool_update",
+            Topic::FeeEstimate => "fee_estimate",
+            Topic::MempoolTx => "mempool_tx",
+        }
+    }
+}
+
+impl FromStr for Topic {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Topic::ALL
+            .into_iter()
+            .find(|topic| topic.as_str() == s)
+            .ok_or_else(|| {
+                format!(
+                    "Unknown topic {}, expected one of new_block, reorg, mempool_update, fee_estimate, mempool_tx",
+                    s
+                )
+            })
+    }
+}
+
+/// Notifications pushed to live clients, serialized as `{"type": <topic>, ...}`.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChainEvent {
+    NewBlock {
+        height: i64,
+        hash: String,
+        prev_hash: String,
+        timestamp: i64,
+        tx_count: u32,
+        size: u32,
+        /// Satoshis
+        total_fee: i64,
+    },
+    Reorg {
+        /// Highest height still shared with the previous chain
+        fork_height: i64,
+        orphaned_blocks: u64,
+    },
+    MempoolUpdate {
+        tx_count: u64,
+        vsize: u64,
+        /// Satoshis
+        total_fee: i64,
+    },
+    /// Feerates in sat/vB needed to confirm within one, three and six blocks
+    FeeEstimate {
+        fastest: f64,
+        half_hour: f64,
+        hour: f64,
+        minimum: f64,
+    },
+    /// A transaction relayed by the node over ZMQ. Only guaranteed to be a mempool entry when a
+    /// `zmqpubsequence` endpoint is configured; otherwise it may already be confirmed.
+    MempoolTx {
+        txid: String,
+        vsize: u64,
+        /// Satoshis
+        output_value: u64,
+    },
+}
+
+impl ChainEvent {
+    pub fn topic(&self) -> Topic {
+        match self {
+            ChainEvent::NewBlock { .. } => Topic::NewBlock,
+            ChainEvent::Reorg { .. } => Topic::Reorg,
+            ChainEvent::MempoolUpdate { .. } => Topic::MempoolUpdate,
+            ChainEvent::FeeEstimate { .. } => Topic::FeeEstimate,
+            ChainEvent::MempoolTx { .. } => Topic::MempoolTx,
+        }
+    }
+}
+
+/// An event with the id used for `Last-Event-ID` replay.
+#[derive(Clone, Debug)]
+pub struct EventEnvelope {
+    pub id: u64,
+    pub event: ChainEvent,
+}
+
+struct History {
+    next_id: u64,
+    events: VecDeque<EventEnvelope>,
+}
+
+/// Fans ingestion events out to every connected client and keeps the most recent ones so
+/// reconnecting clients can catch up.
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<EventEnvelope>,
+    history: Arc<Mutex<History>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        // Start from the current time in milliseconds so ids keep increasing across restarts
+        // and a stale `Last-Event-ID` never matches an unrelated event
+        let next_id = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64);
+        EventBus {
+            sender,
+            history: Arc::new(Mutex::new(History {
+                next_id,
+                events: VecDeque::with_capacity(HISTORY_SIZE),
+            })),
+        }
+    }
+
+    pub fn publish(&self, event: ChainEvent) {
+        let mut history = self.history.lock().unwrap();
+        let envelope = EventEnvelope {
+            id: history.next_id,
+            event,
+        };
+        history.next_id += 1;
+        // Individual transactions arrive fast enough to flush block notifications out of the
+        // buffer, so they are only delivered live
+        if envelope.event.topic() != Topic::MempoolTx {
+            if history.events.len() == HISTORY_SIZE {
+                history.events.pop_front();
+            }
+            history.events.push_back(envelope.clone());
+        }
+        // Sending only fails when nobody is subscribed, which is fine
+        let _ = self.sender.send(envelope);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<EventEnvelope> {
+        self.sender.subscribe()
+    }
+
+    /// Returns the buffered events after `last_id` together with a receiver for everything
+    /// published afterwards, without gaps or duplicates between the two.
+    pub fn subscribe_from(
+        &self,
+        last_id: u64,
+    ) -> (Vec<EventEnvelope>, broadcast::Receiver<EventEnvelope>) {
+        let history = self.history.lock().unwrap();
+        let missed = history
+            .events
+            .iter()
+            .filter(|envelope| envelope.id > last_id)
+            .cloned()
+            .collect();
+        (missed, self.sender.subscribe())
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::broadcast::error::TryRecvError;
+
+    fn reorg(fork_height: i64) -> ChainEvent {
+        ChainEvent::Reorg {
+            fork_height,
+            orphaned_blocks: 1,
+        }
+    }
+
+    fn fork_heights(envelopes: &[EventEnvelope]) -> Vec<i64> {
+        envelopes
+            .iter()
+            .map(|envelope| match envelope.event {
+                ChainEvent::Reorg { fork_height, .. } => fork_height,
+                _ => panic!("unexpected event {:?}", envelope.event),
+            })
+            .collect()
+    }
+
+    fn first_id(bus: &EventBus) -> u64 {
+        bus.history.lock().unwrap().next_id
+    }
+
+    #[test]
+    fn replays_events_after_last_id_without_gaps_or_duplicates() {
+        let bus = EventBus::new();
+        let first = first_id(&bus);
+        for height in 1..=3 {
+            bus.publish(reorg(height));
+        }
+
+        let (missed, mut receiver) = bus.subscribe_from(first);
+        assert_eq!(fork_heights(&missed), vec![2, 3]);
+        assert_eq!(missed[0].id, first + 1);
+
+        bus.publish(reorg(4));
+        let live = receiver.try_recv().unwrap();
+        assert_eq!(live.id, first + 3);
+        assert_eq!(fork_heights(&[live]), vec![4]);
+        assert!(matches!(receiver.try_recv(), Err(TryRecvError::Empty)));
+
+        let (missed, _) = bus.subscribe_from(first + 3);
+        assert!(missed.is_empty());
+    }
+
+    #[test]
+    fn mempool_transactions_are_delivered_live_only() {
+        let bus = EventBus::new();
+        let first = first_id(&bus);
+        let mut receiver = bus.subscribe();
+        bus.publish(ChainEvent::MempoolTx {
+            txid: "aa".repeat(32),
+            vsize: 100,
+            output_value: 1000,
+        });
+        bus.publish(reorg(1));
+
+        assert_eq!(receiver.try_recv().unwrap().event.topic(), Topic::MempoolTx);
+        assert_eq!(receiver.try_recv().unwrap().event.topic(), Topic::Reorg);
+
+        let (missed, _) = bus.subscribe_from(first.saturating_sub(1));
+        assert_eq!(missed.len(), 1);
+        assert_eq!(missed[0].id, first + 1);
+    }
+
+    #[test]
+    fn history_keeps_the_most_recent_events() {
+        let bus = EventBus::new();
+        let first = first_id(&bus);
+        let total = HISTORY_SIZE as i64 + 5;
+        for height in 0..total {
+            bus.publish(reorg(height));
+        }
+
+        // An id older than the retained history replays everything that is left
+        let (missed, _) = bus.subscribe_from(first);
+        assert_eq!(missed.len(), HISTORY_SIZE);
+        assert_eq!(missed[0].id, first + 5);
+        assert_eq!(fork_heights(&missed).last(), Some(&(total - 1)));
+
+        let (missed, _) = bus.subscribe_from(first + total as u64 - 3);
+        assert_eq!(fork_heights(&missed), vec![total - 2, total - 1]);
+    }
+}
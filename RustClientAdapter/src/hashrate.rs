@@ -0,0 +1,74 @@
+use crate::difficulty::RETARGET_INTERVAL;
+use crate::telemetry;
+use serde::Serialize;
+use tokio_postgres::Client;
+
+/// Longest window the hashrate can be averaged over, in blocks
+pub const MAX_HASHRATE_WINDOW: i64 = RETARGET_INTERVAL;
+/// Expected hashes per unit of difficulty
+const HASHES_PER_DIFFICULTY: f64 = 4_294_967_296.0;
+
+#[derive(Serialize)]
+pub struct HashratePoint {
+    pub block_height: i64,
+    pub block_timestamp: i64,
+    /// Estimated hashes per second over the window ending at this block
+    pub hashrate: f64,
+    /// Mean seconds between blocks over the window
+    pub avg_block_interval: f64,
+}
+
+/// Estimates network hashrate like `getnetworkhashps`: the work of the `window` blocks ending at
+/// each point, divided by the time between the earliest and latest timestamp in the window.
+/// Points are at heights divisible by `step`, so they stay put as new blocks arrive, newest first
+/// and below `before` when given. Windows with blocks missing from the database are left out
+/// before the limit applies, so a short page means there are no more points.
+pub async fn load_hashrate(
+    client: &Client,
+    window: i64,
+    step: i64,
+    before: Option<i64>,
+    limit: i64,
+) -> Result<Vec<HashratePoint>, tokio_postgres::Error> {
+    let rows = telemetry::observe_db(
+        "load_hashrate",
+        client.query(
+            "SELECT p.block_height, p.block_timestamp, w.work, w.first_time, w.last_time
+             FROM blockchain_metrics p
+             CROSS JOIN LATERAL (
+                SELECT COUNT(*) AS block_count,
+                       SUM(m.difficulty) FILTER (WHERE m.block_height > p.block_height - $1) AS work,
+                       MIN(m.block_timestamp) AS first_time,
+                       MAX(m.block_timestamp) AS last_time
+                FROM blockchain_metrics m
+                WHERE m.block_height BETWEEN p.block_height - $1 AND p.block_height
+             ) w
+             WHERE p.block_height % $2 = 0
+               AND ($3::BIGINT IS NULL OR p.block_height < $3)
+               AND p.block_timestamp IS NOT NULL
+               AND w.block_count > $1
+               AND w.work IS NOT NULL
+               AND w.last_time > w.first_time
+             ORDER BY p.block_height DESC
+             LIMIT $4",
+            &[&window, &step, &before, &limit],
+        ),
+    )
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let work: f64 = row.get("work");
+            let first_time: i64 = row.get("first_time");
+            let last_time: i64 = row.get("last_time");
+            let elapsed = (last_time - first_time) as f64;
+            HashratePoint {
+                block_height: row.get("block_height"),
+                block_timestamp: row.get("block_timestamp"),
+                hashrate: work * HASHES_PER_DIFFICULTY / elapsed,
+                avg_block_interval: elapsed / window as f64,
+            }
+        })
+        .collect())
+}
@@ -0,0 +1,164 @@
+use crate::config::HealthConfig;
+use crate::data_provider::BitcoinDataProvider;
+use crate::telemetry;
+use deadpool_postgres::Pool;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+use tokio::time::timeout;
+
+/// Longest a single dependency check may take. Leaves room for the blockchain.info rate limit.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(15);
+
+#[derive(Serialize)]
+pub struct ReadinessReport {
+    /// `ok` when every dependency is healthy, `degraded` otherwise
+    pub status: &'static str,
+    pub database: DatabaseCheck,
+    pub provider: ProviderCheck,
+    pub ingestion: IngestionCheck,
+}
+
+#[derive(Serialize)]
+pub struct DatabaseCheck {
+    pub status: &'static str,
+    pub latency_ms: u64,
+    /// Open connections in the pool
+    pub pool_size: usize,
+    /// Open connections not currently checked out
+    pub pool_idle: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct ProviderCheck {
+    pub status: &'static str,
+    pub backend: &'static str,
+    /// 0 when the check reused a recently reported tip
+    pub latency_ms: u64,
+    /// Whether the block count came from a tip reported within the check TTL, with no provider
+    /// error since, instead of the provider
+    pub cached: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block_count: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct IngestionCheck {
+    pub status: &'static str,
+    pub lag_blocks: i64,
+    pub lag_seconds: f64,
+    pub max_lag_blocks: i64,
+}
+
+impl ReadinessReport {
+    pub fn is_ready(&self) -> bool {
+        self.status == "ok"
+    }
+}
+
+/// Checks the database, the data provider and ingestion lag concurrently. The provider is only
+/// asked for the block count when no tip was reported within the configured TTL or a provider
+/// request failed since, so frequent probes don't add to its load or use up the blockchain.info
+/// rate limit while an outage still shows up on the next probe.
+pub async fn check_readiness(
+    pool: &Pool,
+    provider: &BitcoinDataProvider,
+    config: &HealthConfig,
+) -> ReadinessReport {
+    let (database, provider) = tokio::join!(
+        check_database(pool),
+        check_provider(provider, Duration::from_secs(config.provider_check_ttl_secs))
+    );
+    let ingestion = check_ingestion(config, provider.block_count);
+    let ready = database.status == "ok" && provider.status == "ok" && ingestion.status == "ok";
+    ReadinessReport {
+        status: if ready { "ok" } else { "degraded" },
+        database,
+        provider,
+        ingestion,
+    }
+}
+
+async fn check_database(pool: &Pool) -> DatabaseCheck {
+    let started = Instant::now();
+    let ping = async {
+        let client = pool.get().await.map_err(|e| e.to_string())?;
+        client
+            .simple_query("SELECT 1")
+            .await
+            .map_err(|e| e.to_string())
+    };
+    let error = match timeout(CHECK_TIMEOUT, ping).await {
+        Ok(Ok(_)) => None,
+        Ok(Err(e)) => Some(e),
+        Err(_) => Some(format!("No answer within {} seconds", CHECK_TIMEOUT.as_secs())),
+    };
+    let pool_status = pool.status();
+    DatabaseCheck {
+        status: status(error.is_none()),
+        latency_ms: started.elapsed().as_millis() as u64,
+        pool_size: pool_status.size,
+        pool_idle: pool_status.available,
+        error,
+    }
+}
+
+async fn check_provider(provider: &BitcoinDataProvider, ttl: Duration) -> ProviderCheck {
+    if let Some(tip) = telemetry::recent_chain_tip(ttl) {
+        return ProviderCheck {
+            status: status(true),
+            backend: provider.backend(),
+            latency_ms: 0,
+            cached: true,
+            block_count: Some(tip),
+            error: None,
+        };
+    }
+
+    let started = Instant::now();
+    let (block_count, error) = match timeout(CHECK_TIMEOUT, provider.get_block_count()).await {
+        Ok(Ok(count)) => {
+            telemetry::record_chain_tip(count);
+            (Some(count), None)
+        }
+        Ok(Err(e)) => (None, Some(e.to_string())),
+        Err(_) => (
+            None,
+            Some(format!("No answer within {} seconds", CHECK_TIMEOUT.as_secs())),
+        ),
+    };
+    ProviderCheck {
+        status: status(error.is_none()),
+        backend: provider.backend(),
+        latency_ms: started.elapsed().as_millis() as u64,
+        cached: false,
+        block_count,
+        error,
+    }
+}
+
+/// Measures lag against the tip the provider reported within the TTL. A stalled ingestion loop
+/// stops refreshing the tip, so the check then asks the provider and still catches the stall.
+fn check_ingestion(config: &HealthConfig, tip: Option<i64>) -> IngestionCheck {
+    let lag_blocks = match tip {
+        Some(tip) => (tip - telemetry::ingested_height()).max(0),
+        None => telemetry::ingestion_lag_blocks(),
+    };
+    IngestionCheck {
+        status: status(lag_blocks <= config.max_ingestion_lag_blocks),
+        lag_blocks,
+        lag_seconds: telemetry::ingestion_lag_seconds(),
+        max_lag_blocks: config.max_ingestion_lag_blocks,
+    }
+}
+
+fn status(healthy: bool) -> &'static str {
+    if healthy {
+        "ok"
+    } else {
+        "fail"
+    }
+}
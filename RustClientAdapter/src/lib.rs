@@ -0,0 +1,19 @@
+pub mod address;
+pub mod backfill;
+pub mod bitcoin_api;
+pub mod bitcoin_rpc;
+pub mod config;
+pub mod data_provider;
+pub mod db;
+pub mod difficulty;
+pub mod events;
+pub mod hashrate;
+pub mod health;
+pub mod mempool;
+pub mod metrics;
+pub mod migrations;
+pub mod projections;
+pub mod search;
+pub mod server;
+pub mod telemetry;
+pub mod zmq_listener;
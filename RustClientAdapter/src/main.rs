@@ -1,20 +1,15 @@
-mod bitcoin_api;
-mod bitcoin_rpc;
-mod config;
-mod data_provider;
-mod db;
-mod metrics;
-mod server;
-
-use config::Config;
-use data_provider::BitcoinDataProvider;
-use db::{connect_to_postgres_with_retry, setup_database};
 use dotenv::dotenv;
-use log::error;
-use sqlx::PgPool;
-use std::panic;
-use std::process::Command;
+use log::{error, warn};
+use rust_client_adapter::config::{Config, DatabaseConfig};
+use rust_client_adapter::data_provider::BitcoinDataProvider;
+use rust_client_adapter::db::{create_pool, get_connection_with_retry, setup_database};
+use rust_client_adapter::events::EventBus;
+use rust_client_adapter::migrations::{migration_status, run_migrations, verify_migrations, MigrationStatus};
+use rust_client_adapter::zmq_listener::spawn_zmq_listeners;
+use rust_client_adapter::{backfill, mempool, metrics, server};
+use std::env;
 use std::sync::Arc;
+use tokio::sync::Notify;
 use tokio::time::{sleep, Duration};
 
 #[tokio::main]
@@ -22,32 +17,113 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     dotenv().ok();
     env_logger::init();
 
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        None => {}
+        Some("migrate") => {
+            return run_migrate_command(args.get(1).map_or("up", String::as_str)).await;
+        }
+        Some(other) => return Err(format!("Unknown command {}, expected `migrate`", other).into()),
+    }
+
     let config = Config::from_env();
-    setup_database(&config.db_config).await?;
-    let db = Arc::new(connect_to_postgres_with_retry(&config.db_config).await?);
-    let db_clone = db.clone();
+    let pool = setup_database(&config.db_config).await?;
+
+    let bitcoin_provider = Arc::new(
+        BitcoinDataProvider::new(config.use_api, &config.rpc_config)
+            .await
+            .map_err(|e| -> Box<dyn std::error::Error> {
+                error!("Failed to connect to the Bitcoin data source: {}", e);
+                e
+            })?,
+    );
+
+    let events = EventBus::new();
+    let block_notify = Arc::new(Notify::new());
+
+    if config.zmq_config.is_enabled() {
+        if config.use_api {
+            warn!("ZMQ endpoints are ignored when USE_API=true");
+        } else {
+            spawn_zmq_listeners(&config.zmq_config, block_notify.clone(), events.clone());
+        }
+    }
+
+    if config.backfill_config.enabled {
+        let provider = bitcoin_provider.clone();
+        let pool = pool.clone();
+        let backfill_config = config.backfill_config;
+        tokio::spawn(async move {
+            if let Err(e) = backfill::run_backfill(&provider, pool, &backfill_config).await {
+                error!("Backfill error: {}", e);
+            }
+        });
+    }
 
-    let bitcoin_provider = BitcoinDataProvider::new(config.use_api);
+    tokio::spawn(mempool::run_mempool_sampler(
+        bitcoin_provider.clone(),
+        pool.clone(),
+        events.clone(),
+        Duration::from_secs(config.mempool_sample_interval_secs),
+        Duration::from_secs(config.mempool_histogram_interval_secs),
+    ));
 
-    let server_handle = tokio::spawn(async move {
-        if let Err(e) = server::start_server(db_clone).await {
+    let server_pool = pool.clone();
+    let server_provider = bitcoin_provider.clone();
+    let server_events = events.clone();
+    let health_config = config.health_config.clone();
+    tokio::spawn(async move {
+        if let Err(e) = server::start_server(server_pool, server_provider, server_events, health_config).await {
             error!("Server error: {:?}", e);
         }
     });
 
     // Main loop for processing metrics
     loop {
-        if let Err(e) = metrics::process_and_store_metrics(&bitcoin_provider, db.clone()).await {
+        if let Err(e) = metrics::process_and_store_metrics(&bitcoin_provider, &pool, &events, &block_notify).await {
             error!("Error processing metrics: {}", e);
         }
         sleep(Duration::from_secs(60)).await;
     }
+}
 
-    // This line will never be reached in the current setup,
-    // but it's good practice to include it
-    if let Err(e) = server_handle.await {
-        error!("Server task error: {:?}", e);
+/// `migrate up` applies pending migrations, `migrate status` lists every migration and
+/// `migrate verify` fails when applied migrations differ from the ones in this build.
+async fn run_migrate_command(action: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let pool = create_pool(&DatabaseConfig::from_env())?;
+    let mut client = get_connection_with_retry(&pool).await?;
+    match action {
+        "up" => {
+            let applied = run_migrations(&mut client).await?;
+            if applied.is_empty() {
+                println!("No pending migrations");
+            }
+            for version in applied {
+                println!("Applied migration {}", version);
+            }
+        }
+        "status" => print_migrations(&migration_status(&client).await?),
+        "verify" => {
+            let statuses = verify_migrations(&client).await?;
+            print_migrations(&statuses);
+            println!("Applied migrations match this build");
+        }
+        other => {
+            return Err(format!("Unknown migrate action {}, expected up, status or verify", other).into())
+        }
     }
-
     Ok(())
 }
+
+fn print_migrations(statuses: &[MigrationStatus]) {
+    println!("{:<8} {:<24} {:<9} applied_at", "version", "name", "state");
+    for status in statuses {
+        println!(
+            "{:<8} {:<24} {:<9} {}",
+            status.version,
+            status.name,
+            status.state.to_string(),
+            status.applied_at.as_deref().unwrap_or("-")
+        );
+    }
+}
@@ -0,0 +1,152 @@
+use crate::data_provider::{BitcoinDataProvider, MempoolEntry, MempoolInfo};
+use crate::events::{ChainEvent, EventBus};
+use crate::telemetry;
+use deadpool_postgres::Pool;
+use log::{error, info};
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::time::{sleep, Duration};
+use tokio_postgres::Client;
+
+/// Lower bounds of the feerate histogram buckets in sat/vB.
+const FEERATE_BUCKETS: [f64; 18] = [
+    0.0, 1.0, 2.0, 3.0, 5.0, 8.0, 10.0, 15.0, 20.0, 30.0, 50.0, 75.0, 100.0, 150.0, 200.0, 300.0,
+    500.0, 1000.0,
+];
+
+/// Block space in vbytes used to project which mempool transactions confirm next
+const BLOCK_VSIZE: u64 = 1_000_000;
+/// Floor for fee estimates when the backend does not report a minimum relay feerate, in sat/vB
+const DEFAULT_MIN_FEERATE: f64 = 1.0;
+
+#[derive(Serialize)]
+pub struct FeerateBucket {
+    pub min_feerate: f64,
+    pub tx_count: u64,
+    pub vsize: u64,
+}
+
+/// Periodically samples the mempool, appends a row to `mempool_snapshots` and publishes
+/// the mempool size. Every mempool entry is only listed once per `histogram_interval`, for the
+/// feerate histogram and fee estimates, since that is far more work for the node.
+pub async fn run_mempool_sampler(
+    provider: Arc<BitcoinDataProvider>,
+    pool: Pool,
+    events: EventBus,
+    interval: Duration,
+    histogram_interval: Duration,
+) {
+    let mut last_histogram: Option<Instant> = None;
+    loop {
+        let with_entries = last_histogram.is_none_or(|at| at.elapsed() >= histogram_interval);
+        match sample_mempool(&provider, &pool, &events, with_entries).await {
+            Ok(true) => last_histogram = Some(Instant::now()),
+            Ok(false) => {}
+            Err(e) => error!("Error sampling mempool: {}", e),
+        }
+        sleep(interval).await;
+    }
+}
+
+/// Returns whether the sample included the mempool entries.
+async fn sample_mempool(
+    provider: &BitcoinDataProvider,
+    pool: &Pool,
+    events: &EventBus,
+    with_entries: bool,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let mempool = provider.get_mempool_info(with_entries).await?;
+    insert_snapshot(&*pool.get().await?, &mempool).await?;
+    events.publish(ChainEvent::MempoolUpdate {
+        tx_count: mempool.tx_count,
+        vsize: mempool.vsize,
+        total_fee: mempool.total_fee,
+    });
+    if let Some(entries) = &mempool.entries {
+        events.publish(estimate_fees(entries, mempool.min_relay_feerate));
+    }
+    info!(
+        "Stored mempool snapshot - Tx count: {}, vsize: {}, Total fee: {}",
+        mempool.tx_count, mempool.vsize, mempool.total_fee
+    );
+    Ok(mempool.entries.is_some())
+}
+
+pub fn feerate_histogram(entries: &[MempoolEntry]) -> Vec<FeerateBucket> {
+    let mut buckets: Vec<FeerateBucket> = FEERATE_BUCKETS
+        .iter()
+        .map(|&min_feerate| FeerateBucket {
+            min_feerate,
+            tx_count: 0,
+            vsize: 0,
+        })
+        .collect();
+
+    for entry in entries.iter().filter(|entry| entry.vsize > 0) {
+        let feerate = entry.fee as f64 / f64::from(entry.vsize);
+        let index = FEERATE_BUCKETS
+            .iter()
+            .rposition(|&min_feerate| feerate >= min_feerate)
+            .unwrap_or(0);
+        buckets[index].tx_count += 1;
+        buckets[index].vsize += u64::from(entry.vsize);
+    }
+    buckets
+}
+
+/// Projects the next blocks by filling them with the highest-feerate transactions and takes the
+/// feerate of the last transaction that fits into the first one, three and six blocks.
+pub fn estimate_fees(entries: &[MempoolEntry], min_relay_feerate: Option<f64>) -> ChainEvent {
+    let minimum = min_relay_feerate.unwrap_or(DEFAULT_MIN_FEERATE);
+    let mut rates: Vec<(f64, u64)> = entries
+        .iter()
+        .filter(|entry| entry.vsize > 0)
+        .map(|entry| (entry.fee as f64 / f64::from(entry.vsize), u64::from(entry.vsize)))
+        .collect();
+    rates.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    let feerate_within = |blocks: u64| {
+        let mut cumulative = 0;
+        for (rate, vsize) in &rates {
+            cumulative += vsize;
+            if cumulative >= blocks * BLOCK_VSIZE {
+                return rate.max(minimum);
+            }
+        }
+        // Everything in the mempool fits, so the minimum relay feerate is enough
+        minimum
+    };
+    ChainEvent::FeeEstimate {
+        fastest: feerate_within(1),
+        half_hour: feerate_within(3),
+        hour: feerate_within(6),
+        minimum,
+    }
+}
+
+async fn insert_snapshot(
+    client: &Client,
+    mempool: &MempoolInfo,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let histogram = match &mempool.entries {
+        Some(entries) => Some(serde_json::to_value(feerate_histogram(entries))?),
+        None => None,
+    };
+    telemetry::observe_db(
+        "insert_snapshot",
+        client.execute(
+            "INSERT INTO mempool_snapshots (tx_count, vsize, total_fee, min_relay_feerate, feerate_histogram)
+             VALUES ($1, $2, $3, $4, $5)",
+            &[
+                &(mempool.tx_count as i64),
+                &(mempool.vsize as i64),
+                &mempool.total_fee,
+                &mempool.min_relay_feerate,
+                &histogram,
+            ],
+        ),
+    )
+    .await?;
+    Ok(())
+}
@@ -1,106 +1,354 @@
 use crate::data_provider::{BitcoinDataProvider, BlockInfo};
-use log::{error, info};
-use std::sync::Arc;
-use tokio_postgres::Client;
-use std::collections::VecDeque;
-use tokio::time::{sleep, Duration};
+use crate::difficulty::difficulty_from_bits;
+use crate::events::{ChainEvent, EventBus};
+use crate::telemetry;
+use log::{error, info, warn};
+use deadpool_postgres::Pool;
+use tokio_postgres::{Client, GenericClient};
+use tokio::sync::Notify;
+use tokio::time::{timeout, Duration};
 
 const BLOCK_HISTORY_SIZE: i64 = 10;
 const UPDATE_INTERVAL: Duration = Duration::from_secs(60); // 1 minute
+const MAX_REORG_DEPTH: i64 = 100;
+const INGESTION_STATE_NAME: &str = "blockchain_metrics";
 
 pub async fn process_and_store_metrics(
     provider: &BitcoinDataProvider,
-    db: Arc<Client>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let mut last_processed_height: i64 = -1;
+    pool: &Pool,
+    events: &EventBus,
+    block_notify: &Notify,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut last_processed_height = match load_ingestion_state(&*pool.get().await?).await? {
+        Some((height, hash)) => {
+            info!("Resuming ingestion after block {} ({})", height, hash);
+            telemetry::record_ingested_height(height);
+            height
+        }
+        None => -1,
+    };
 
     loop {
         let latest_block_height = match provider.get_block_count().await {
             Ok(count) => {
                 info!("Fetched latest block height: {}", count);
+                telemetry::record_chain_tip(count);
                 count
             }
             Err(e) => {
                 error!("Failed to fetch block count: {}", e);
-                return Err(e.into());
+                return Err(e);
             }
         };
 
         if latest_block_height > last_processed_height {
-            // Process new blocks
-            let start_height = std::cmp::max(latest_block_height - BLOCK_HISTORY_SIZE + 1, last_processed_height + 1);
+            // Process new blocks, filling every height missed since the last checkpoint
+            let start_height = if last_processed_height < 0 {
+                std::cmp::max(latest_block_height - BLOCK_HISTORY_SIZE + 1, 0)
+            } else {
+                last_processed_height + 1
+            };
+            let mut db = pool.get().await?;
             for height in start_height..=latest_block_height {
-                process_block(provider, &db, height).await?;
+                process_block(provider, &mut db, events, height).await?;
             }
             last_processed_height = latest_block_height;
         } else {
             info!("No new blocks. Waiting for updates...");
         }
 
-        // Wait before checking for updates again
-        sleep(UPDATE_INTERVAL).await;
+        // Wake up early when ZMQ announces a block, otherwise poll again after the interval
+        let _ = timeout(UPDATE_INTERVAL, block_notify.notified()).await;
     }
 }
 
+/// Stores one block and advances the checkpoint to it. Any rollback, the new rows and the
+/// checkpoint are committed together, so every height up to the checkpoint is always stored.
 async fn process_block(
     provider: &BitcoinDataProvider,
-    db: &Arc<Client>,
+    db: &mut Client,
+    events: &EventBus,
     height: i64,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let block_info = match provider.get_block_info(height).await {
-        Ok(info) => info,
-        Err(e) => {
-            error!("Failed to fetch block info for height {}: {}", height, e);
-            return Err(e.into());
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let block_info = fetch_block_info(provider, height).await?;
+
+    // The new block must build on the block we stored at height - 1, otherwise the chain reorganized
+    let mut reorg = None;
+    if let Some(stored_prev_hash) = get_stored_block_hash(db, height - 1).await? {
+        if stored_prev_hash != block_info.prev_hash {
+            warn!(
+                "Chain reorganization detected at height {}: stored hash {} does not match previous hash {}",
+                height - 1,
+                stored_prev_hash,
+                block_info.prev_hash
+            );
+            reorg = Some(fetch_reorg(provider, db, height - 1).await?);
         }
-    };
+    }
+    let connection_count = fetch_connection_count(provider).await?;
 
-    let difficulty = match provider.get_difficulty().await {
-        Ok(diff) => diff,
+    let transaction = db.transaction().await?;
+    let mut orphaned = 0;
+    if let Some(reorg) = &reorg {
+        orphaned = mark_stale_blocks(&transaction, reorg.fork_height).await?;
+        for branch_block in &reorg.branch {
+            store_block(&transaction, branch_block, connection_count).await?;
+        }
+    }
+    store_block(&transaction, &block_info, connection_count).await?;
+    save_ingestion_state(&transaction, height, &block_info.hash).await?;
+    transaction.commit().await?;
+    info!("Processed and inserted metrics for block height: {}", height);
+
+    if let Some(reorg) = reorg {
+        warn!(
+            "Rolled back {} orphaned block(s) above fork point {}",
+            orphaned, reorg.fork_height
+        );
+        events.publish(ChainEvent::Reorg {
+            fork_height: reorg.fork_height,
+            orphaned_blocks: orphaned,
+        });
+        for branch_block in &reorg.branch {
+            publish_new_block(events, branch_block);
+        }
+    }
+    publish_new_block(events, &block_info);
+    telemetry::record_ingested_height(height);
+    Ok(())
+}
+
+async fn fetch_block_info(
+    provider: &BitcoinDataProvider,
+    height: i64,
+) -> Result<BlockInfo, Box<dyn std::error::Error + Send + Sync>> {
+    match provider.get_block_info(height).await {
+        Ok(info) => Ok(info),
         Err(e) => {
-            error!("Failed to fetch difficulty: {}", e);
-            return Err(e.into());
+            error!("Failed to fetch block info for height {}: {}", height, e);
+            Err(e)
         }
-    };
+    }
+}
 
-    let connection_count = match provider.get_connection_count().await {
-        Ok(count) => count,
+async fn fetch_connection_count(
+    provider: &BitcoinDataProvider,
+) -> Result<i32, Box<dyn std::error::Error + Send + Sync>> {
+    match provider.get_connection_count().await {
+        Ok(count) => Ok(count as i32),
         Err(e) => {
             error!("Failed to fetch connection count: {}", e);
-            return Err(e.into());
+            Err(e)
         }
-    };
+    }
+}
+
+/// Blocks of the winning branch that replace the stored ones above `fork_height`
+struct Reorg {
+    fork_height: i64,
+    branch: Vec<BlockInfo>,
+}
+
+/// Walks back from `mismatch_height` to the fork point and fetches the winning branch up to and
+/// including `mismatch_height`. Nothing is written, so a provider error leaves the stored chain as is.
+async fn fetch_reorg(
+    provider: &BitcoinDataProvider,
+    db: &Client,
+    mismatch_height: i64,
+) -> Result<Reorg, Box<dyn std::error::Error + Send + Sync>> {
+    let fork_height = find_fork_point(provider, db, mismatch_height).await?;
+    let mut branch = Vec::new();
+    for height in fork_height + 1..=mismatch_height {
+        branch.push(fetch_block_info(provider, height).await?);
+    }
+    Ok(Reorg {
+        fork_height,
+        branch,
+    })
+}
 
-    insert_metrics(
-        db,
-        height,
-        difficulty,
-        connection_count as i32,
-        &block_info,
+/// Returns the highest height at or below `start_height` whose stored hash matches the active chain.
+async fn find_fork_point(
+    provider: &BitcoinDataProvider,
+    db: &Client,
+    start_height: i64,
+) -> Result<i64, Box<dyn std::error::Error + Send + Sync>> {
+    let mut height = start_height;
+    while height >= 0 {
+        if start_height - height > MAX_REORG_DEPTH {
+            return Err(format!(
+                "Reorganization deeper than {} blocks below height {}",
+                MAX_REORG_DEPTH, start_height
+            )
+            .into());
+        }
+        let stored_hash = match get_stored_block_hash(db, height).await? {
+            Some(hash) => hash,
+            None => return Ok(height),
+        };
+        if stored_hash == provider.get_block_hash(height).await? {
+            return Ok(height);
+        }
+        height -= 1;
+    }
+    Ok(height)
+}
+
+/// Writes every row of one block. Callers pass a transaction so a block is never half stored.
+pub(crate) async fn store_block(
+    db: &impl GenericClient,
+    block_info: &BlockInfo,
+    connection_count: i32,
+) -> Result<(), tokio_postgres::Error> {
+    insert_metrics(db, block_info.height, connection_count, block_info).await?;
+    insert_transactions(db, block_info).await?;
+    insert_address_index(db, block_info).await?;
+    insert_fee_stats(db, block_info).await?;
+    Ok(())
+}
+
+fn publish_new_block(events: &EventBus, block_info: &BlockInfo) {
+    events.publish(ChainEvent::NewBlock {
+        height: block_info.height,
+        hash: block_info.hash.clone(),
+        prev_hash: block_info.prev_hash.clone(),
+        timestamp: block_info.timestamp,
+        tx_count: block_info.tx_count,
+        size: block_info.size,
+        total_fee: block_info.fee_stats.total_fee,
+    });
+}
+
+async fn get_stored_block_hash(
+    client: &Client,
+    block_height: i64,
+) -> Result<Option<String>, tokio_postgres::Error> {
+    let row = telemetry::observe_db(
+        "get_stored_block_hash",
+        client.query_opt(
+            "SELECT block_hash FROM blockchain_metrics WHERE block_height = $1",
+            &[&block_height],
+        ),
     )
     .await?;
+    Ok(row.and_then(|row| row.get("block_hash")))
+}
 
-    info!("Processed and inserted metrics for block height: {}", height);
+async fn load_ingestion_state(
+    client: &Client,
+) -> Result<Option<(i64, String)>, tokio_postgres::Error> {
+    let row = telemetry::observe_db(
+        "load_ingestion_state",
+        client.query_opt(
+            "SELECT last_height, last_hash FROM ingestion_state WHERE name = $1",
+            &[&INGESTION_STATE_NAME],
+        ),
+    )
+    .await?;
+    Ok(row.map(|row| (row.get("last_height"), row.get("last_hash"))))
+}
+
+async fn save_ingestion_state(
+    client: &impl GenericClient,
+    last_height: i64,
+    last_hash: &str,
+) -> Result<(), tokio_postgres::Error> {
+    telemetry::observe_db(
+        "save_ingestion_state",
+        client.execute(
+            "INSERT INTO ingestion_state (name, last_height, last_hash)
+             VALUES ($1, $2, $3)
+             ON CONFLICT (name)
+             DO UPDATE SET
+                last_height = EXCLUDED.last_height,
+                last_hash = EXCLUDED.last_hash,
+                updated_at = CURRENT_TIMESTAMP",
+            &[&INGESTION_STATE_NAME, &last_height, &last_hash],
+        ),
+    )
+    .await?;
     Ok(())
 }
 
-async fn insert_metrics(
-    client: &Arc<Client>,
+/// Moves every stored block above `fork_height` into `stale_blocks` and returns how many were moved.
+async fn mark_stale_blocks(
+    client: &impl GenericClient,
+    fork_height: i64,
+) -> Result<u64, tokio_postgres::Error> {
+    telemetry::observe_db(
+        "mark_stale_blocks",
+        client.execute(
+            "DELETE FROM transactions WHERE block_height > $1",
+            &[&(fork_height as i32)],
+        ),
+    )
+    .await?;
+    telemetry::observe_db(
+        "mark_stale_blocks",
+        client.execute(
+            "DELETE FROM block_fee_stats WHERE block_height > $1",
+            &[&fork_height],
+        ),
+    )
+    .await?;
+    telemetry::observe_db(
+        "mark_stale_blocks",
+        client.execute(
+            "DELETE FROM address_outputs WHERE block_height > $1",
+            &[&fork_height],
+        ),
+    )
+    .await?;
+    telemetry::observe_db(
+        "mark_stale_blocks",
+        client.execute(
+            "DELETE FROM address_inputs WHERE block_height > $1",
+            &[&fork_height],
+        ),
+    )
+    .await?;
+    telemetry::observe_db(
+        "mark_stale_blocks",
+        client.execute(
+            "DELETE FROM address_index_gaps WHERE block_height > $1",
+            &[&fork_height],
+        ),
+    )
+    .await?;
+    telemetry::observe_db(
+        "mark_stale_blocks",
+        client.execute(
+            "WITH orphaned AS (
+                DELETE FROM blockchain_metrics WHERE block_height > $1
+                RETURNING block_height, block_hash, tx_count, block_size, block_timestamp
+             )
+             INSERT INTO stale_blocks (block_height, block_hash, tx_count, block_size, block_timestamp)
+             SELECT block_height, block_hash, tx_count, block_size, block_timestamp
+             FROM orphaned WHERE block_hash IS NOT NULL
+             ON CONFLICT (block_hash) DO NOTHING",
+            &[&fork_height],
+        ),
+    )
+    .await
+}
+
+pub(crate) async fn insert_metrics(
+    client: &impl GenericClient,
     block_height: i64,
-    difficulty: f64,
     connection_count: i32,
     block_info: &BlockInfo,
 ) -> Result<(), tokio_postgres::Error> {
+    let difficulty = difficulty_from_bits(block_info.bits);
     info!(
         "Inserting metrics - Block Height: {}, Difficulty: {}, Connection count: {}, Tx count: {}, Size: {}, Timestamp: {}",
         block_height, difficulty, connection_count, block_info.tx_count, block_info.size, block_info.timestamp
     );
-    
-    let difficulty_str = difficulty.to_string();
-    client
-        .execute(
-            "INSERT INTO blockchain_metrics (block_height, difficulty, connection_count, tx_count, block_size, block_timestamp, block_hash) 
-             VALUES ($1, $2, $3, $4, $5, $6, $7)
+
+    telemetry::observe_db(
+        "insert_metrics",
+        client.execute(
+            "INSERT INTO blockchain_metrics (block_height, difficulty, connection_count, tx_count, block_size, block_timestamp, block_hash, prev_hash, version, merkle_root, nonce, bits) 
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
              ON CONFLICT (block_height) 
              DO UPDATE SET 
                 difficulty = EXCLUDED.difficulty,
@@ -108,9 +356,254 @@ async fn insert_metrics(
                 tx_count = EXCLUDED.tx_count,
                 block_size = EXCLUDED.block_size,
                 block_timestamp = EXCLUDED.block_timestamp,
-                block_hash = EXCLUDED.block_hash",
-            &[&block_height, &difficulty_str, &connection_count, &(block_info.tx_count as i32), &(block_info.size as i32), &block_info.timestamp, &block_info.hash],
+                block_hash = EXCLUDED.block_hash,
+                prev_hash = EXCLUDED.prev_hash,
+                version = EXCLUDED.version,
+                merkle_root = EXCLUDED.merkle_root,
+                nonce = EXCLUDED.nonce,
+                bits = EXCLUDED.bits",
+            &[
+                &block_height,
+                &difficulty,
+                &connection_count,
+                &(block_info.tx_count as i32),
+                &(block_info.size as i32),
+                &block_info.timestamp,
+                &block_info.hash,
+                &block_info.prev_hash,
+                &block_info.version,
+                &block_info.merkle_root,
+                &i64::from(block_info.nonce),
+                &i64::from(block_info.bits),
+            ],
+        ),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Writes every transaction of the block in a single statement, replacing earlier rows for the same txid.
+pub(crate) async fn insert_transactions(
+    client: &impl GenericClient,
+    block_info: &BlockInfo,
+) -> Result<(), tokio_postgres::Error> {
+    if block_info.transactions.is_empty() {
+        return Ok(());
+    }
+
+    let txs = &block_info.transactions;
+    let txids: Vec<&str> = txs.iter().map(|tx| tx.txid.as_str()).collect();
+    let positions: Vec<i32> = txs.iter().map(|tx| tx.position as i32).collect();
+    let vsizes: Vec<i32> = txs.iter().map(|tx| tx.vsize as i32).collect();
+    let weights: Vec<i32> = txs.iter().map(|tx| tx.weight as i32).collect();
+    let fees: Vec<Option<i64>> = txs.iter().map(|tx| tx.fee).collect();
+    let input_counts: Vec<i32> = txs.iter().map(|tx| tx.input_count as i32).collect();
+    let output_counts: Vec<i32> = txs.iter().map(|tx| tx.output_count as i32).collect();
+    let segwit_flags: Vec<bool> = txs.iter().map(|tx| tx.is_segwit).collect();
+    let taproot_flags: Vec<bool> = txs.iter().map(|tx| tx.is_taproot).collect();
+
+    telemetry::observe_db(
+        "insert_transactions",
+        client.execute(
+            "INSERT INTO transactions (txid, block_height, position, vsize, weight, fee, input_count, output_count, is_segwit, is_taproot)
+             SELECT txid, $1, position, vsize, weight, fee, input_count, output_count, is_segwit, is_taproot
+             FROM UNNEST($2::text[], $3::int[], $4::int[], $5::int[], $6::bigint[], $7::int[], $8::int[], $9::bool[], $10::bool[])
+                AS t(txid, position, vsize, weight, fee, input_count, output_count, is_segwit, is_taproot)
+             ON CONFLICT (txid)
+             DO UPDATE SET
+                block_height = EXCLUDED.block_height,
+                position = EXCLUDED.position,
+                vsize = EXCLUDED.vsize,
+                weight = EXCLUDED.weight,
+                fee = EXCLUDED.fee,
+                input_count = EXCLUDED.input_count,
+                output_count = EXCLUDED.output_count,
+                is_segwit = EXCLUDED.is_segwit,
+                is_taproot = EXCLUDED.is_taproot",
+            &[
+                &(block_info.height as i32),
+                &txids,
+                &positions,
+                &vsizes,
+                &weights,
+                &fees,
+                &input_counts,
+                &output_counts,
+                &segwit_flags,
+                &taproot_flags,
+            ],
+        ),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Records which addresses each transaction pays to and spends from, replacing earlier rows.
+/// Inputs whose spent output the backend did not return, e.g. from a node older than v25, can't
+/// be attributed, so the block is listed in `address_index_gaps` until it is indexed in full.
+pub(crate) async fn insert_address_index(
+    client: &impl GenericClient,
+    block_info: &BlockInfo,
+) -> Result<(), tokio_postgres::Error> {
+    let mut output_txids = Vec::new();
+    let mut output_vouts = Vec::new();
+    let mut output_addresses = Vec::new();
+    let mut output_values = Vec::new();
+    let mut input_txids = Vec::new();
+    let mut input_vins = Vec::new();
+    let mut input_addresses = Vec::new();
+    let mut input_values = Vec::new();
+    let mut prev_txids = Vec::new();
+    let mut prev_vouts = Vec::new();
+    for tx in &block_info.transactions {
+        for (vout, output) in tx.outputs.iter().enumerate() {
+            if let Some(address) = &output.address {
+                output_txids.push(tx.txid.as_str());
+                output_vouts.push(vout as i32);
+                output_addresses.push(address.as_str());
+                output_values.push(output.value);
+            }
+        }
+        for input in &tx.inputs {
+            if let Some(address) = &input.address {
+                input_txids.push(tx.txid.as_str());
+                input_vins.push(input.vin as i32);
+                input_addresses.push(address.as_str());
+                input_values.push(input.value);
+                prev_txids.push(input.prev_txid.as_str());
+                prev_vouts.push(input.prev_vout as i32);
+            }
+        }
+    }
+
+    if !output_txids.is_empty() {
+        telemetry::observe_db(
+            "insert_address_index",
+            client.execute(
+                "INSERT INTO address_outputs (txid, vout, address, value, block_height)
+                 SELECT txid, vout, address, value, $1
+                 FROM UNNEST($2::text[], $3::int[], $4::text[], $5::bigint[]) AS t(txid, vout, address, value)
+                 ON CONFLICT (txid, vout)
+                 DO UPDATE SET
+                    address = EXCLUDED.address,
+                    value = EXCLUDED.value,
+                    block_height = EXCLUDED.block_height",
+                &[&block_info.height, &output_txids, &output_vouts, &output_addresses, &output_values],
+            ),
         )
         .await?;
+    }
+    if !input_txids.is_empty() {
+        telemetry::observe_db(
+            "insert_address_index",
+            client.execute(
+                "INSERT INTO address_inputs (txid, vin, address, value, block_height, prev_txid, prev_vout)
+                 SELECT txid, vin, address, value, $1, prev_txid, prev_vout
+                 FROM UNNEST($2::text[], $3::int[], $4::text[], $5::bigint[], $6::text[], $7::int[])
+                    AS t(txid, vin, address, value, prev_txid, prev_vout)
+                 ON CONFLICT (txid, vin)
+                 DO UPDATE SET
+                    address = EXCLUDED.address,
+                    value = EXCLUDED.value,
+                    block_height = EXCLUDED.block_height,
+                    prev_txid = EXCLUDED.prev_txid,
+                    prev_vout = EXCLUDED.prev_vout",
+                &[
+                    &block_info.height,
+                    &input_txids,
+                    &input_vins,
+                    &input_addresses,
+                    &input_values,
+                    &prev_txids,
+                    &prev_vouts,
+                ],
+            ),
+        )
+        .await?;
+    }
+    record_address_index_gap(client, block_info).await
+}
+
+async fn record_address_index_gap(
+    client: &impl GenericClient,
+    block_info: &BlockInfo,
+) -> Result<(), tokio_postgres::Error> {
+    let missing: u32 = block_info
+        .transactions
+        .iter()
+        .map(|tx| tx.missing_prevouts)
+        .sum();
+    if missing == 0 {
+        telemetry::observe_db(
+            "record_address_index_gap",
+            client.execute(
+                "DELETE FROM address_index_gaps WHERE block_height = $1",
+                &[&block_info.height],
+            ),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    warn!(
+        "Block {} has {} input(s) without prevout data; address balances will count those outputs as unspent",
+        block_info.height, missing
+    );
+    telemetry::observe_db(
+        "record_address_index_gap",
+        client.execute(
+            "INSERT INTO address_index_gaps (block_height, missing_inputs)
+             VALUES ($1, $2)
+             ON CONFLICT (block_height)
+             DO UPDATE SET
+                missing_inputs = EXCLUDED.missing_inputs,
+                recorded_at = CURRENT_TIMESTAMP",
+            &[&block_info.height, &(missing as i32)],
+        ),
+    )
+    .await?;
+    Ok(())
+}
+
+pub(crate) async fn insert_fee_stats(
+    client: &impl GenericClient,
+    block_info: &BlockInfo,
+) -> Result<(), tokio_postgres::Error> {
+    let stats = &block_info.fee_stats;
+    let [p10, p25, p50, p75, p90] = stats.feerate_percentiles;
+    telemetry::observe_db(
+        "insert_fee_stats",
+        client.execute(
+            "INSERT INTO block_fee_stats (block_height, block_hash, total_fee, subsidy, min_feerate, feerate_p10, feerate_p25, feerate_p50, feerate_p75, feerate_p90, max_feerate)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+             ON CONFLICT (block_height)
+             DO UPDATE SET
+                block_hash = EXCLUDED.block_hash,
+                total_fee = EXCLUDED.total_fee,
+                subsidy = EXCLUDED.subsidy,
+                min_feerate = EXCLUDED.min_feerate,
+                feerate_p10 = EXCLUDED.feerate_p10,
+                feerate_p25 = EXCLUDED.feerate_p25,
+                feerate_p50 = EXCLUDED.feerate_p50,
+                feerate_p75 = EXCLUDED.feerate_p75,
+                feerate_p90 = EXCLUDED.feerate_p90,
+                max_feerate = EXCLUDED.max_feerate,
+                updated_at = CURRENT_TIMESTAMP",
+            &[
+                &block_info.height,
+                &block_info.hash,
+                &stats.total_fee,
+                &stats.subsidy,
+                &stats.min_feerate,
+                &p10,
+                &p25,
+                &p50,
+                &p75,
+                &p90,
+                &stats.max_feerate,
+            ],
+        ),
+    )
+    .await?;
     Ok(())
 }
@@ -0,0 +1,275 @@
+use bitcoincore_rpc::bitcoin::hashes::{sha256, Hash};
+use log::info;
+use std::collections::HashMap;
+use std::fmt;
+use thiserror::Error;
+use tokio_postgres::Client;
+
+/// Serializes migration runs between instances starting at the same time
+const MIGRATION_LOCK_ID: i64 = 0x6274_635f_6d69_6772;
+
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+/// Every schema change in the order it is applied. Applied migrations must never be edited;
+/// add a new one instead. The early ones only use `IF NOT EXISTS` statements so they also apply
+/// over databases created before versions were tracked.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "blockchain_metrics",
+        sql: include_str!("../sql/migrations/0001_blockchain_metrics.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "stale_blocks",
+        sql: include_str!("../sql/migrations/0002_stale_blocks.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "backfill_checkpoint",
+        sql: include_str!("../sql/migrations/0003_backfill_checkpoint.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "ingestion_state",
+        sql: include_str!("../sql/migrations/0004_ingestion_state.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "transactions",
+        sql: include_str!("../sql/migrations/0005_transactions.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "block_fee_stats",
+        sql: include_str!("../sql/migrations/0006_block_fee_stats.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "mempool_snapshots",
+        sql: include_str!("../sql/migrations/0007_mempool_snapshots.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "block_header_fields",
+        sql: include_str!("../sql/migrations/0008_block_header_fields.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "address_index",
+        sql: include_str!("../sql/migrations/0009_address_index.sql"),
+    },
+    Migration {
+        version: 10,
+        name: "numeric_difficulty",
+        sql: include_str!("../sql/migrations/0010_numeric_difficulty.sql"),
+    },
+    Migration {
+        version: 11,
+        name: "optional_mempool_histogram",
+        sql: include_str!("../sql/migrations/0011_optional_mempool_histogram.sql"),
+    },
+    Migration {
+        version: 12,
+        name: "address_index_gaps",
+        sql: include_str!("../sql/migrations/0012_address_index_gaps.sql"),
+    },
+];
+
+impl Migration {
+    pub fn checksum(&self) -> String {
+        sha256::Hash::hash(self.sql.as_bytes()).to_string()
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum MigrationError {
+    #[error("Database error: {0}")]
+    Database(#[from] tokio_postgres::Error),
+    #[error("Migration {version} ({name}) was changed after it was applied")]
+    ChecksumMismatch { version: i64, name: String },
+    #[error("Database has migration {0} which this build does not know about")]
+    UnknownVersion(i64),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MigrationState {
+    Applied,
+    Pending,
+    /// Applied, but the embedded SQL no longer matches the recorded checksum
+    Modified,
+    /// Recorded in the database but missing from this build
+    Unknown,
+}
+
+impl fmt::Display for MigrationState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MigrationState::Applied => "applied",
+            MigrationState::Pending => "pending",
+            MigrationState::Modified => "modified",
+            MigrationState::Unknown => "unknown",
+        })
+    }
+}
+
+pub struct MigrationStatus {
+    pub version: i64,
+    pub name: String,
+    pub state: MigrationState,
+    /// `applied_at` as recorded by Postgres
+    pub applied_at: Option<String>,
+}
+
+struct AppliedMigration {
+    name: String,
+    checksum: String,
+    applied_at: String,
+}
+
+async fn ensure_migrations_table(client: &Client) -> Result<(), tokio_postgres::Error> {
+    client
+        .batch_execute(
+            "CREATE TABLE IF NOT EXISTS schema_migrations (
+                version BIGINT PRIMARY KEY,
+                name TEXT NOT NULL,
+                checksum TEXT NOT NULL,
+                applied_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+            )",
+        )
+        .await
+}
+
+async fn load_applied(
+    client: &Client,
+) -> Result<HashMap<i64, AppliedMigration>, tokio_postgres::Error> {
+    let rows = client
+        .query(
+            "SELECT version, name, checksum, applied_at::TEXT AS applied_at FROM schema_migrations",
+            &[],
+        )
+        .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            (
+                row.get("version"),
+                AppliedMigration {
+                    name: row.get("name"),
+                    checksum: row.get("checksum"),
+                    applied_at: row.get("applied_at"),
+                },
+            )
+        })
+        .collect())
+}
+
+/// Compares the embedded migrations with `schema_migrations`, ordered by version.
+pub async fn migration_status(client: &Client) -> Result<Vec<MigrationStatus>, MigrationError> {
+    ensure_migrations_table(client).await?;
+    let mut applied = load_applied(client).await?;
+
+    let mut statuses: Vec<MigrationStatus> = MIGRATIONS
+        .iter()
+        .map(|migration| match applied.remove(&migration.version) {
+            Some(record) => MigrationStatus {
+                version: migration.version,
+                name: migration.name.to_string(),
+                state: if record.checksum == migration.checksum() {
+                    MigrationState::Applied
+                } else {
+                    MigrationState::Modified
+                },
+                applied_at: Some(record.applied_at),
+            },
+            None => MigrationStatus {
+                version: migration.version,
+                name: migration.name.to_string(),
+                state: MigrationState::Pending,
+                applied_at: None,
+            },
+        })
+        .collect();
+    statuses.extend(applied.into_iter().map(|(version, record)| MigrationStatus {
+        version,
+        name: record.name,
+        state: MigrationState::Unknown,
+        applied_at: Some(record.applied_at),
+    }));
+    statuses.sort_by_key(|status| status.version);
+    Ok(statuses)
+}
+
+/// Fails if an applied migration was edited or the database is ahead of this build.
+pub async fn verify_migrations(client: &Client) -> Result<Vec<MigrationStatus>, MigrationError> {
+    let statuses = migration_status(client).await?;
+    for status in &statuses {
+        match status.state {
+            MigrationState::Modified => {
+                return Err(MigrationError::ChecksumMismatch {
+                    version: status.version,
+                    name: status.name.clone(),
+                })
+            }
+            MigrationState::Unknown => return Err(MigrationError::UnknownVersion(status.version)),
+            MigrationState::Applied | MigrationState::Pending => {}
+        }
+    }
+    Ok(statuses)
+}
+
+/// Verifies the applied migrations and applies the pending ones, each in its own transaction.
+/// Returns the versions that were applied.
+pub async fn run_migrations(client: &mut Client) -> Result<Vec<i64>, MigrationError> {
+    let statuses = verify_migrations(client).await?;
+    let mut applied = Vec::new();
+    for migration in MIGRATIONS {
+        let pending = statuses.iter().any(|status| {
+            status.version == migration.version && status.state == MigrationState::Pending
+        });
+        if pending && apply_migration(client, migration).await? {
+            applied.push(migration.version);
+        }
+    }
+    Ok(applied)
+}
+
+/// Returns false when another instance applied the migration first.
+async fn apply_migration(
+    client: &mut Client,
+    migration: &Migration,
+) -> Result<bool, tokio_postgres::Error> {
+    let transaction = client.transaction().await?;
+    // Data migrations may run longer than the pool's statement timeout
+    transaction
+        .batch_execute("SET LOCAL statement_timeout = 0")
+        .await?;
+    transaction
+        .execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK_ID])
+        .await?;
+    let already_applied = transaction
+        .query_opt(
+            "SELECT 1 FROM schema_migrations WHERE version = $1",
+            &[&migration.version],
+        )
+        .await?
+        .is_some();
+    if already_applied {
+        return Ok(false);
+    }
+
+    info!("Applying migration {} ({})", migration.version, migration.name);
+    transaction.batch_execute(migration.sql).await?;
+    transaction
+        .execute(
+            "INSERT INTO schema_migrations (version, name, checksum) VALUES ($1, $2, $3)",
+            &[&migration.version, &migration.name, &migration.checksum()],
+        )
+        .await?;
+    transaction.commit().await?;
+    Ok(true)
+}
@@ -0,0 +1,141 @@
+use crate::data_provider::{block_subsidy, HALVING_INTERVAL};
+use crate::difficulty::{projected_adjustment, RETARGET_INTERVAL, TARGET_BLOCK_SPACING};
+use crate::telemetry;
+use serde::Serialize;
+use tokio_postgres::Client;
+
+#[derive(Serialize)]
+pub struct ChainProjections {
+    /// Highest stored block the projections start from
+    pub tip_height: i64,
+    pub tip_time: i64,
+    pub epoch: EpochProjection,
+    pub halving: HalvingProjection,
+}
+
+#[derive(Serialize)]
+pub struct EpochProjection {
+    pub epoch: i64,
+    pub start_height: i64,
+    /// First block mined at the new difficulty
+    pub next_retarget_height: i64,
+    pub blocks_mined: i64,
+    pub blocks_remaining: i64,
+    pub progress_percent: f64,
+    pub difficulty: f64,
+    /// Mean seconds between the stored blocks of this epoch, absent until two are stored
+    pub avg_block_interval: Option<f64>,
+    pub projected_difficulty: Option<f64>,
+    pub projected_change_percent: Option<f64>,
+    /// Unix timestamp, at the observed block interval or the 10 minute target without one
+    pub estimated_retarget_time: i64,
+}
+
+#[derive(Serialize)]
+pub struct HalvingProjection {
+    pub next_halving_height: i64,
+    pub blocks_remaining: i64,
+    /// Unix timestamp, at the same block interval as the retarget estimate
+    pub estimated_halving_time: i64,
+    /// Subsidy of the tip block in satoshis
+    pub current_subsidy: i64,
+    pub next_subsidy: i64,
+}
+
+/// First height after `tip_height` mined at a halved subsidy.
+fn next_halving_height(tip_height: i64) -> i64 {
+    (tip_height / HALVING_INTERVAL + 1) * HALVING_INTERVAL
+}
+
+/// Projects the next difficulty adjustment and halving from the stored chain. The block interval
+/// is measured from the first stored block of the current epoch, which is its first block once
+/// ingestion covers the whole epoch. Returns `None` while no blocks with timestamps are stored.
+pub async fn load_chain_projections(
+    client: &Client,
+) -> Result<Option<ChainProjections>, tokio_postgres::Error> {
+    let row = telemetry::observe_db(
+        "load_chain_projections",
+        client.query_opt(
+            "SELECT t.block_height, t.block_timestamp, t.difficulty,
+                    s.block_height AS first_height, s.block_timestamp AS first_time
+             FROM (
+                SELECT block_height, block_timestamp, difficulty FROM blockchain_metrics
+                WHERE block_timestamp IS NOT NULL
+                ORDER BY block_height DESC LIMIT 1
+             ) t
+             CROSS JOIN LATERAL (
+                SELECT block_height, block_timestamp FROM blockchain_metrics
+                WHERE block_height >= t.block_height / $1 * $1 AND block_timestamp IS NOT NULL
+                ORDER BY block_height LIMIT 1
+             ) s",
+            &[&RETARGET_INTERVAL],
+        ),
+    )
+    .await?;
+    let row = match row {
+        Some(row) => row,
+        None => return Ok(None),
+    };
+
+    let tip_height: i64 = row.get("block_height");
+    let tip_time: i64 = row.get("block_timestamp");
+    let difficulty: f64 = row.get("difficulty");
+    let first_height: i64 = row.get("first_height");
+    let first_time: i64 = row.get("first_time");
+
+    let epoch = tip_height / RETARGET_INTERVAL;
+    let start_height = epoch * RETARGET_INTERVAL;
+    let next_retarget_height = start_height + RETARGET_INTERVAL;
+    let blocks_mined = tip_height - start_height + 1;
+    let blocks_remaining = next_retarget_height - tip_height;
+
+    let avg_block_interval = if tip_height > first_height {
+        Some((tip_time - first_time) as f64 / (tip_height - first_height) as f64)
+    } else {
+        None
+    };
+    let adjustment = avg_block_interval.map(projected_adjustment);
+    let interval = avg_block_interval.unwrap_or(TARGET_BLOCK_SPACING as f64);
+    let estimate = |blocks: i64| tip_time + (blocks as f64 * interval).round() as i64;
+
+    let next_halving_height = next_halving_height(tip_height);
+    let halving_blocks_remaining = next_halving_height - tip_height;
+
+    Ok(Some(ChainProjections {
+        tip_height,
+        tip_time,
+        epoch: EpochProjection {
+            epoch,
+            start_height,
+            next_retarget_height,
+            blocks_mined,
+            blocks_remaining,
+            progress_percent: blocks_mined as f64 / RETARGET_INTERVAL as f64 * 100.0,
+            difficulty,
+            avg_block_interval,
+            projected_difficulty: adjustment.map(|factor| difficulty * factor),
+            projected_change_percent: adjustment.map(|factor| (factor - 1.0) * 100.0),
+            estimated_retarget_time: estimate(blocks_remaining),
+        },
+        halving: HalvingProjection {
+            next_halving_height,
+            blocks_remaining: halving_blocks_remaining,
+            estimated_halving_time: estimate(halving_blocks_remaining),
+            current_subsidy: block_subsidy(tip_height),
+            next_subsidy: block_subsidy(next_halving_height),
+        },
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn next_halving_is_the_following_interval_boundary() {
+        assert_eq!(next_halving_height(0), 210_000);
+        assert_eq!(next_halving_height(209_999), 210_000);
+        // The halving block itself already has the reduced subsidy
+        assert_eq!(next_halving_height(210_000), 420_000);
+    }
+}
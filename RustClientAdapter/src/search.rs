@@ -0,0 +1,219 @@
+use crate::address::{parse_address, AddressError};
+use bitcoincore_rpc::bitcoin::{base58, Address, Network, NetworkKind};
+use serde::Serialize;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Length of a serialized BIP32 extended key
+const EXTENDED_KEY_LEN: usize = 78;
+
+/// Version bytes of extended public keys with the script type their prefix implies (SLIP-132).
+const XPUB_VERSIONS: [([u8; 4], &str, NetworkKind, &str); 6] = [
+    ([0x04, 0x88, 0xb2, 0x1e], "xpub", NetworkKind::Main, "p2pkh"),
+    ([0x04, 0x9d, 0x7c, 0xb2], "ypub", NetworkKind::Main, "p2sh-p2wpkh"),
+    ([0x04, 0xb2, 0x47, 0x46], "zpub", NetworkKind::Main, "p2wpkh"),
+    ([0x04, 0x35, 0x87, 0xcf], "tpub", NetworkKind::Test, "p2pkh"),
+    ([0x04, 0x4a, 0x52, 0x62], "upub", NetworkKind::Test, "p2sh-p2wpkh"),
+    ([0x04, 0x5f, 0x1c, 0xf6], "vpub", NetworkKind::Test, "p2wpkh"),
+];
+
+#[derive(Error, Debug)]
+pub enum SearchError {
+    #[error("Search query must not be empty")]
+    Empty,
+    #[error("Unrecognized search query: {0}")]
+    Unrecognized(String),
+    #[error(transparent)]
+    Address(#[from] AddressError),
+    #[error("Extended key {0} is not valid on {1}, the network this explorer is configured for")]
+    WrongNetwork(String, Network),
+}
+
+/// What a search query looks like before anything is looked up.
+pub enum SearchQuery {
+    Height(i64),
+    /// 64 hex characters, either a block hash or a txid
+    Hash(String),
+    Address(Address),
+    Xpub(XpubSummary),
+}
+
+#[derive(Serialize)]
+pub struct XpubSummary {
+    pub xpub: String,
+    /// `xpub`, `ypub`, `zpub`, `tpub`, `upub` or `vpub`
+    pub prefix: &'static str,
+    /// Script type implied by the prefix
+    pub script_type: &'static str,
+    pub depth: u8,
+}
+
+/// Classifies user input for `/search` and validates addresses and extended keys
+/// against `network`.
+pub fn classify(query: &str, network: Network) -> Result<SearchQuery, SearchError> {
+    let query = query.trim();
+    if query.is_empty() {
+        return Err(SearchError::Empty);
+    }
+    // Checked before heights since a hash may consist of digits only
+    if query.len() == 64 && query.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Ok(SearchQuery::Hash(query.to_ascii_lowercase()));
+    }
+    if query.chars().all(|c| c.is_ascii_digit()) {
+        return query
+            .parse()
+            .map(SearchQuery::Height)
+            .map_err(|_| SearchError::Unrecognized(query.to_string()));
+    }
+    if let Some(xpub) = parse_xpub(query, network)? {
+        return Ok(SearchQuery::Xpub(xpub));
+    }
+    if Address::from_str(query).is_ok() {
+        return Ok(SearchQuery::Address(parse_address(query, network)?));
+    }
+    Err(SearchError::Unrecognized(query.to_string()))
+}
+
+/// Returns `None` when `query` is not an extended public key at all.
+fn parse_xpub(query: &str, network: Network) -> Result<Option<XpubSummary>, SearchError> {
+    let data = match base58::decode_check(query) {
+        Ok(data) if data.len() == EXTENDED_KEY_LEN => data,
+        _ => return Ok(None),
+    };
+    let Some(&(_, prefix, kind, script_type)) = XPUB_VERSIONS
+        .iter()
+        .find(|(version, ..)| data[..4] == version[..])
+    else {
+        return Ok(None);
+    };
+    if kind != NetworkKind::from(network) {
+        return Err(SearchError::WrongNetwork(query.to_string(), network));
+    }
+    Ok(Some(XpubSummary {
+        xpub: query.to_string(),
+        prefix,
+        script_type,
+        depth: data[4],
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoincore_rpc::bitcoin::AddressType;
+
+    /// BIP32 test vector 1, master key
+    const XPUB: &str = "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8";
+
+    /// Re-encodes `XPUB` with the version bytes of another prefix.
+    fn with_prefix(prefix: &str) -> String {
+        let (version, ..) = XPUB_VERSIONS
+            .iter()
+            .find(|(_, name, ..)| *name == prefix)
+            .unwrap();
+        let mut data = base58::decode_check(XPUB).unwrap();
+        data[..4].copy_from_slice(version);
+        base58::encode_check(&data)
+    }
+
+    #[test]
+    fn digits_only_hash_is_not_a_height() {
+        let digits = "0".repeat(60) + "1234";
+        match classify(&digits, Network::Bitcoin).unwrap() {
+            SearchQuery::Hash(hash) => assert_eq!(hash, digits),
+            _ => panic!("expected a hash"),
+        }
+        assert!(matches!(
+            classify(" 840000 ", Network::Bitcoin).unwrap(),
+            SearchQuery::Height(840_000)
+        ));
+    }
+
+    #[test]
+    fn hashes_are_lowercased() {
+        let hash = "AB".repeat(32);
+        match classify(&hash, Network::Bitcoin).unwrap() {
+            SearchQuery::Hash(hash) => assert_eq!(hash, "ab".repeat(32)),
+            _ => panic!("expected a hash"),
+        }
+    }
+
+    #[test]
+    fn rejects_empty_and_unrecognized_queries() {
+        assert!(matches!(classify("  ", Network::Bitcoin), Err(SearchError::Empty)));
+        assert!(matches!(
+            classify("99999999999999999999", Network::Bitcoin),
+            Err(SearchError::Unrecognized(_))
+        ));
+        assert!(matches!(
+            classify("not a thing", Network::Bitcoin),
+            Err(SearchError::Unrecognized(_))
+        ));
+    }
+
+    #[test]
+    fn classifies_bech32_and_base58_addresses() {
+        let cases = [
+            ("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq", AddressType::P2wpkh),
+            ("1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2", AddressType::P2pkh),
+            ("3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy", AddressType::P2sh),
+        ];
+        for (address, address_type) in cases {
+            match classify(address, Network::Bitcoin).unwrap() {
+                SearchQuery::Address(parsed) => {
+                    assert_eq!(parsed.address_type(), Some(address_type));
+                    assert_eq!(parsed.to_string(), address);
+                }
+                _ => panic!("expected an address for {}", address),
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_addresses_from_another_network() {
+        assert!(matches!(
+            classify("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx", Network::Bitcoin),
+            Err(SearchError::Address(AddressError::WrongNetwork { .. }))
+        ));
+        assert!(matches!(
+            classify("1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2", Network::Testnet),
+            Err(SearchError::Address(AddressError::WrongNetwork { .. }))
+        ));
+    }
+
+    #[test]
+    fn classifies_extended_key_prefixes() {
+        for (prefix, script_type) in [
+            ("xpub", "p2pkh"),
+            ("ypub", "p2sh-p2wpkh"),
+            ("zpub", "p2wpkh"),
+        ] {
+            let key = with_prefix(prefix);
+            assert!(key.starts_with(prefix));
+            match classify(&key, Network::Bitcoin).unwrap() {
+                SearchQuery::Xpub(summary) => {
+                    assert_eq!(summary.prefix, prefix);
+                    assert_eq!(summary.script_type, script_type);
+                    assert_eq!(summary.depth, 0);
+                }
+                _ => panic!("expected an extended key for {}", prefix),
+            }
+        }
+        match classify(&with_prefix("vpub"), Network::Signet).unwrap() {
+            SearchQuery::Xpub(summary) => assert_eq!(summary.prefix, "vpub"),
+            _ => panic!("expected an extended key"),
+        }
+    }
+
+    #[test]
+    fn rejects_extended_keys_from_another_network() {
+        assert!(matches!(
+            classify(XPUB, Network::Testnet),
+            Err(SearchError::WrongNetwork(_, Network::Testnet))
+        ));
+        assert!(matches!(
+            classify(&with_prefix("tpub"), Network::Bitcoin),
+            Err(SearchError::WrongNetwork(_, Network::Bitcoin))
+        ));
+    }
+}
@@ -1,17 +1,125 @@
+use crate::address::{load_address_info, parse_address, AddressError};
+use crate::config::HealthConfig;
+use crate::data_provider::{
+    AddressInfo, BitcoinDataProvider, BlockInfo, NotFoundError, ProviderError, TransactionDetail,
+};
+use crate::difficulty::{difficulty_from_bits, load_difficulty_epochs, DifficultyEpoch};
+use crate::events::{EventBus, EventEnvelope, Topic};
+use crate::hashrate::{load_hashrate, HashratePoint, MAX_HASHRATE_WINDOW};
+use crate::health::{check_readiness, ReadinessReport};
+use crate::projections::{load_chain_projections, ChainProjections};
+use crate::search::{classify, SearchError, SearchQuery, XpubSummary};
+use crate::telemetry;
 use axum::{
-    extract::Extension,
-    response::Json,
+    extract::{
+        rejection::{PathRejection, QueryRejection},
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Extension, MatchedPath, Path, Query, Request,
+    },
+    middleware::{self, Next},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Json, Response,
+    },
     routing::get,
     Router,
 };
-use http::Method;
-use serde::Serialize;
+use deadpool_postgres::{Pool, PoolError};
+use futures::{future, stream, Stream, StreamExt};
+use http::{header, HeaderMap, Method, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeSet;
+use std::convert::Infallible;
 use std::sync::Arc;
+use std::time::Instant;
+use thiserror::Error;
 use tokio::net::TcpListener;
-use tokio_postgres::Client;
+use tokio::sync::broadcast::{error::RecvError, Receiver};
 use tower_http::cors::{Any, CorsLayer};
 use std::error::Error;
-use log::{error, info};
+use log::{error, info, warn};
+
+/// Error returned by HTTP handlers, rendered as `{"error": code, "message": ...}`.
+#[derive(Error, Debug)]
+pub enum ApiError {
+    #[error("Database query failed: {0}")]
+    Database(#[from] tokio_postgres::Error),
+    #[error("Database unavailable: {0}")]
+    Pool(#[from] PoolError),
+    #[error("Bitcoin data provider failed: {0}")]
+    Provider(ProviderError),
+    #[error("{0}")]
+    Validation(String),
+    #[error("{0}")]
+    NotFound(String),
+}
+
+impl From<ProviderError> for ApiError {
+    fn from(e: ProviderError) -> Self {
+        match e.downcast::<NotFoundError>() {
+            Ok(not_found) => ApiError::NotFound(not_found.to_string()),
+            Err(e) => ApiError::Provider(e),
+        }
+    }
+}
+
+impl ApiError {
+    fn status(&self) -> StatusCode {
+        match self {
+            ApiError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::Pool(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Provider(_) => StatusCode::BAD_GATEWAY,
+            ApiError::Validation(_) => StatusCode::BAD_REQUEST,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            ApiError::Database(_) => "database_error",
+            ApiError::Pool(_) => "database_unavailable",
+            ApiError::Provider(_) => "provider_error",
+            ApiError::Validation(_) => "invalid_request",
+            ApiError::NotFound(_) => "not_found",
+        }
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(rejection: QueryRejection) -> Self {
+        ApiError::Validation(rejection.body_text())
+    }
+}
+
+impl From<AddressError> for ApiError {
+    fn from(e: AddressError) -> Self {
+        ApiError::Validation(e.to_string())
+    }
+}
+
+impl From<SearchError> for ApiError {
+    fn from(e: SearchError) -> Self {
+        ApiError::Validation(e.to_string())
+    }
+}
+
+impl From<PathRejection> for ApiError {
+    fn from(rejection: PathRejection) -> Self {
+        ApiError::Validation(rejection.body_text())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        if status.is_server_error() {
+            error!("Request failed: {}", self);
+        }
+        let body = Json(json!({ "error": self.code(), "message": self.to_string() }));
+        (status, body).into_response()
+    }
+}
 
 #[derive(Serialize)]
 struct Transaction {
@@ -30,11 +138,229 @@ struct BlockchainMetric {
     block_timestamp: Option<i64>,
     block_hash: Option<String>,
 }
-async fn get_transactions(Extension(client): Extension<Arc<Client>>) -> Json<Vec<Transaction>> {
-    let rows = client
-        .query("SELECT txid, block_height FROM transactions", &[])
-        .await
-        .expect("Failed to execute query");
+
+const DEFAULT_PAGE_LIMIT: i64 = 100;
+const MAX_PAGE_LIMIT: i64 = 1000;
+/// Response header carrying the `after` value for the next page of `/blockchain_metrics` and
+/// `/hashrate`
+const NEXT_CURSOR_HEADER: &str = "x-next-cursor";
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+/// Filters for `/blockchain_metrics`; times are unix timestamps in seconds and `after`
+/// is the `X-Next-Cursor` header returned with the previous page.
+#[derive(Deserialize)]
+struct MetricsQuery {
+    from_height: Option<i64>,
+    to_height: Option<i64>,
+    from_time: Option<i64>,
+    to_time: Option<i64>,
+    limit: Option<i64>,
+    after: Option<i64>,
+    #[serde(default)]
+    order: SortOrder,
+}
+
+/// Offset pagination for `/address/{addr}`, matching what blockchain.info supports.
+#[derive(Deserialize)]
+struct AddressQuery {
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+const DEFAULT_ADDRESS_PAGE_LIMIT: i64 = 50;
+
+/// Rolling window and spacing for `/hashrate`, both in blocks. Points sit at heights divisible by
+/// `step`, which defaults to `window`, and `after` is the `X-Next-Cursor` header returned with the
+/// previous page.
+#[derive(Deserialize)]
+struct HashrateQuery {
+    window: Option<i64>,
+    step: Option<i64>,
+    limit: Option<i64>,
+    after: Option<i64>,
+}
+
+const DEFAULT_HASHRATE_WINDOW: i64 = 144;
+
+#[derive(Deserialize)]
+struct SearchParams {
+    q: String,
+}
+
+/// The lookup a search query resolved to, serialized as `{"type": ..., "result": ...}`.
+#[derive(Serialize)]
+#[serde(tag = "type", content = "result", rename_all = "snake_case")]
+enum SearchHit {
+    Block(BlockDetail),
+    Transaction(TransactionDetail),
+    Address(AddressInfo),
+    Xpub(XpubSummary),
+}
+
+#[derive(Serialize)]
+struct SearchResponse {
+    query: String,
+    /// Path of the detail endpoint for the hit, if there is one
+    redirect: Option<String>,
+    #[serde(flatten)]
+    hit: SearchHit,
+}
+
+/// `topics` is a comma-separated list for `/ws` and `/events`.
+#[derive(Deserialize)]
+struct TopicParams {
+    topics: Option<String>,
+}
+
+/// Messages clients may send over `/ws` to change their subscription.
+#[derive(Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+enum WsCommand {
+    Subscribe { topics: Vec<Topic> },
+    Unsubscribe { topics: Vec<Topic> },
+}
+
+#[derive(Serialize)]
+struct BlockFeeStat {
+    block_height: i64,
+    block_hash: String,
+    total_fee: i64,
+    subsidy: i64,
+    min_feerate: f64,
+    feerate_p10: f64,
+    feerate_p25: f64,
+    feerate_p50: f64,
+    feerate_p75: f64,
+    feerate_p90: f64,
+    max_feerate: f64,
+}
+
+/// A single block with its header fields, served from `blockchain_metrics` when stored and
+/// fetched from the data provider otherwise.
+#[derive(Serialize)]
+struct BlockDetail {
+    height: i64,
+    hash: String,
+    prev_hash: String,
+    version: i32,
+    merkle_root: String,
+    nonce: i64,
+    /// Compact target as a hex string, e.g. `1d00ffff`
+    bits: String,
+    timestamp: i64,
+    tx_count: i32,
+    size: i32,
+    difficulty: Option<f64>,
+    connection_count: Option<i32>,
+    fee_stats: Option<BlockFeeStat>,
+    /// `database` or `provider`
+    source: &'static str,
+}
+
+impl BlockDetail {
+    fn from_row(row: &tokio_postgres::Row) -> Self {
+        let total_fee: Option<i64> = row.get("total_fee");
+        let fee_stats = total_fee.map(|total_fee| BlockFeeStat {
+            block_height: row.get("block_height"),
+            block_hash: row.get("block_hash"),
+            total_fee,
+            subsidy: row.get("subsidy"),
+            min_feerate: row.get("min_feerate"),
+            feerate_p10: row.get("feerate_p10"),
+            feerate_p25: row.get("feerate_p25"),
+            feerate_p50: row.get("feerate_p50"),
+            feerate_p75: row.get("feerate_p75"),
+            feerate_p90: row.get("feerate_p90"),
+            max_feerate: row.get("max_feerate"),
+        });
+        BlockDetail {
+            height: row.get("block_height"),
+            hash: row.get("block_hash"),
+            prev_hash: row.get("prev_hash"),
+            version: row.get("version"),
+            merkle_root: row.get("merkle_root"),
+            nonce: row.get("nonce"),
+            bits: format!("{:08x}", row.get::<_, i64>("bits")),
+            timestamp: row.get("block_timestamp"),
+            tx_count: row.get("tx_count"),
+            size: row.get("block_size"),
+            difficulty: row.get("difficulty"),
+            connection_count: row.get("connection_count"),
+            fee_stats,
+            source: "database",
+        }
+    }
+
+    fn from_block_info(block: BlockInfo) -> Self {
+        let fee_stats = &block.fee_stats;
+        let [p10, p25, p50, p75, p90] = fee_stats.feerate_percentiles;
+        let fee_stat = BlockFeeStat {
+            block_height: block.height,
+            block_hash: block.hash.clone(),
+            total_fee: fee_stats.total_fee,
+            subsidy: fee_stats.subsidy,
+            min_feerate: fee_stats.min_feerate,
+            feerate_p10: p10,
+            feerate_p25: p25,
+            feerate_p50: p50,
+            feerate_p75: p75,
+            feerate_p90: p90,
+            max_feerate: fee_stats.max_feerate,
+        };
+        BlockDetail {
+            height: block.height,
+            hash: block.hash,
+            prev_hash: block.prev_hash,
+            version: block.version,
+            merkle_root: block.merkle_root,
+            nonce: i64::from(block.nonce),
+            bits: format!("{:08x}", block.bits),
+            timestamp: block.timestamp,
+            tx_count: block.tx_count as i32,
+            size: block.size as i32,
+            difficulty: Some(difficulty_from_bits(block.bits)),
+            connection_count: None,
+            fee_stats: Some(fee_stat),
+            source: "provider",
+        }
+    }
+}
+
+/// Rows written before the header columns existed have NULL `prev_hash` and are treated as a miss.
+const BLOCK_DETAIL_SQL: &str =
+    "SELECT m.block_height, m.block_hash, m.prev_hash, m.version, m.merkle_root, m.nonce, m.bits,
+            m.block_timestamp, m.tx_count, m.block_size, m.difficulty, m.connection_count,
+            f.total_fee, f.subsidy, f.min_feerate, f.feerate_p10, f.feerate_p25, f.feerate_p50,
+            f.feerate_p75, f.feerate_p90, f.max_feerate
+     FROM blockchain_metrics m
+     LEFT JOIN block_fee_stats f ON f.block_height = m.block_height AND f.block_hash = m.block_hash
+     WHERE m.prev_hash IS NOT NULL AND";
+
+#[derive(Serialize)]
+struct MempoolSnapshot {
+    sampled_at: i64,
+    tx_count: i64,
+    vsize: i64,
+    total_fee: i64,
+    min_relay_feerate: Option<f64>,
+    /// Only present on the snapshots that listed every mempool entry
+    feerate_histogram: Option<serde_json::Value>,
+}
+
+async fn get_transactions(Extension(pool): Extension<Pool>) -> Result<Json<Vec<Transaction>>, ApiError> {
+    let client = pool.get().await?;
+    let rows = telemetry::observe_db(
+        "get_transactions",
+        client.query("SELECT txid, block_height FROM transactions ORDER BY block_height DESC, position LIMIT 100", &[]),
+    )
+    .await?;
 
     let transactions: Vec<Transaction> = rows
         .into_iter()
@@ -44,16 +370,65 @@ async fn get_transactions(Extension(client): Extension<Arc<Client>>) -> Json<Vec
         })
         .collect();
 
-    Json(transactions)
+    Ok(Json(transactions))
 }
 async fn get_blockchain_metrics(
-    Extension(client): Extension<Arc<Client>>,
-) -> Json<Vec<BlockchainMetric>> {
-    let rows = client
-        .query("SELECT id, timestamp, block_height, difficulty::float8, connection_count, tx_count, block_size, block_timestamp, block_hash FROM blockchain_metrics ORDER BY block_height DESC LIMIT 100", &[])
-        .await
-        .expect("Failed to execute query");
-    let metrics: Vec<BlockchainMetric> = rows
+    Extension(pool): Extension<Pool>,
+    query: Result<Query<MetricsQuery>, QueryRejection>,
+) -> Result<(HeaderMap, Json<Vec<BlockchainMetric>>), ApiError> {
+    let Query(query) = query?;
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_LIMIT);
+    if !(1..=MAX_PAGE_LIMIT).contains(&limit) {
+        return Err(ApiError::Validation(format!(
+            "limit must be between 1 and {}",
+            MAX_PAGE_LIMIT
+        )));
+    }
+    if let (Some(from), Some(to)) = (query.from_height, query.to_height) {
+        if from > to {
+            return Err(ApiError::Validation("from_height must not exceed to_height".to_string()));
+        }
+    }
+    if let (Some(from), Some(to)) = (query.from_time, query.to_time) {
+        if from > to {
+            return Err(ApiError::Validation("from_time must not exceed to_time".to_string()));
+        }
+    }
+
+    let (direction, cursor_op) = match query.order {
+        SortOrder::Asc => ("ASC", ">"),
+        SortOrder::Desc => ("DESC", "<"),
+    };
+    let sql = format!(
+        "SELECT id, timestamp, block_height, difficulty, connection_count, tx_count, block_size, block_timestamp, block_hash
+         FROM blockchain_metrics
+         WHERE ($1::BIGINT IS NULL OR block_height >= $1)
+           AND ($2::BIGINT IS NULL OR block_height <= $2)
+           AND ($3::BIGINT IS NULL OR block_timestamp >= $3)
+           AND ($4::BIGINT IS NULL OR block_timestamp <= $4)
+           AND ($5::BIGINT IS NULL OR block_height {} $5)
+         ORDER BY block_height {}
+         LIMIT $6",
+        cursor_op, direction
+    );
+    // Fetch one extra row to find out whether there is another page
+    let client = pool.get().await?;
+    let rows = telemetry::observe_db(
+        "get_blockchain_metrics",
+        client.query(
+            &sql,
+            &[
+                &query.from_height,
+                &query.to_height,
+                &query.from_time,
+                &query.to_time,
+                &query.after,
+                &(limit + 1),
+            ],
+        ),
+    )
+    .await?;
+    let mut metrics: Vec<BlockchainMetric> = rows
         .into_iter()
         .map(|row| BlockchainMetric {
             id: row.get("id"),
@@ -66,19 +441,479 @@ async fn get_blockchain_metrics(
             block_hash: row.get("block_hash"),
         })
         .collect();
-    Json(metrics)
+
+    // The body stays a bare array for existing consumers, the cursor goes in a header
+    let mut headers = HeaderMap::new();
+    if metrics.len() as i64 > limit {
+        metrics.truncate(limit as usize);
+        if let Some(last) = metrics.last() {
+            headers.insert(NEXT_CURSOR_HEADER, last.block_height.into());
+        }
+    }
+    Ok((headers, Json(metrics)))
+}
+async fn get_block_fee_stats(
+    Extension(pool): Extension<Pool>,
+) -> Result<Json<Vec<BlockFeeStat>>, ApiError> {
+    let client = pool.get().await?;
+    let rows = telemetry::observe_db(
+        "get_block_fee_stats",
+        client.query("SELECT block_height, block_hash, total_fee, subsidy, min_feerate, feerate_p10, feerate_p25, feerate_p50, feerate_p75, feerate_p90, max_feerate FROM block_fee_stats ORDER BY block_height DESC LIMIT 100", &[]),
+    )
+    .await?;
+    let stats: Vec<BlockFeeStat> = rows
+        .into_iter()
+        .map(|row| BlockFeeStat {
+            block_height: row.get("block_height"),
+            block_hash: row.get("block_hash"),
+            total_fee: row.get("total_fee"),
+            subsidy: row.get("subsidy"),
+            min_feerate: row.get("min_feerate"),
+            feerate_p10: row.get("feerate_p10"),
+            feerate_p25: row.get("feerate_p25"),
+            feerate_p50: row.get("feerate_p50"),
+            feerate_p75: row.get("feerate_p75"),
+            feerate_p90: row.get("feerate_p90"),
+            max_feerate: row.get("max_feerate"),
+        })
+        .collect();
+    Ok(Json(stats))
+}
+async fn get_mempool_history(
+    Extension(pool): Extension<Pool>,
+) -> Result<Json<Vec<MempoolSnapshot>>, ApiError> {
+    let client = pool.get().await?;
+    let rows = telemetry::observe_db(
+        "get_mempool_history",
+        client.query("SELECT EXTRACT(EPOCH FROM sampled_at)::BIGINT AS sampled_at, tx_count, vsize, total_fee, min_relay_feerate, feerate_histogram FROM mempool_snapshots ORDER BY sampled_at DESC LIMIT 100", &[]),
+    )
+    .await?;
+    let snapshots: Vec<MempoolSnapshot> = rows
+        .into_iter()
+        .map(|row| MempoolSnapshot {
+            sampled_at: row.get("sampled_at"),
+            tx_count: row.get("tx_count"),
+            vsize: row.get("vsize"),
+            total_fee: row.get("total_fee"),
+            min_relay_feerate: row.get("min_relay_feerate"),
+            feerate_histogram: row.get("feerate_histogram"),
+        })
+        .collect();
+    Ok(Json(snapshots))
+}
+async fn get_difficulty_epochs(
+    Extension(pool): Extension<Pool>,
+) -> Result<Json<Vec<DifficultyEpoch>>, ApiError> {
+    Ok(Json(load_difficulty_epochs(&*pool.get().await?).await?))
+}
+async fn get_chain_projections(
+    Extension(pool): Extension<Pool>,
+) -> Result<Json<ChainProjections>, ApiError> {
+    load_chain_projections(&*pool.get().await?)
+        .await?
+        .map(Json)
+        .ok_or_else(|| ApiError::NotFound("No blocks have been ingested yet".to_string()))
+}
+async fn get_hashrate(
+    Extension(pool): Extension<Pool>,
+    query: Result<Query<HashrateQuery>, QueryRejection>,
+) -> Result<(HeaderMap, Json<Vec<HashratePoint>>), ApiError> {
+    let Query(query) = query?;
+    let window = query.window.unwrap_or(DEFAULT_HASHRATE_WINDOW);
+    if !(1..=MAX_HASHRATE_WINDOW).contains(&window) {
+        return Err(ApiError::Validation(format!(
+            "window must be between 1 and {}",
+            MAX_HASHRATE_WINDOW
+        )));
+    }
+    let step = query.step.unwrap_or(window);
+    if step < 1 {
+        return Err(ApiError::Validation("step must be at least 1".to_string()));
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_LIMIT);
+    if !(1..=MAX_PAGE_LIMIT).contains(&limit) {
+        return Err(ApiError::Validation(format!(
+            "limit must be between 1 and {}",
+            MAX_PAGE_LIMIT
+        )));
+    }
+
+    // Fetch one extra point to find out whether there is another page
+    let client = pool.get().await?;
+    let mut points = load_hashrate(&client, window, step, query.after, limit + 1).await?;
+    let mut headers = HeaderMap::new();
+    if points.len() as i64 > limit {
+        points.truncate(limit as usize);
+        if let Some(last) = points.last() {
+            headers.insert(NEXT_CURSOR_HEADER, last.block_height.into());
+        }
+    }
+    Ok((headers, Json(points)))
+}
+async fn get_block_by_height(
+    Extension(pool): Extension<Pool>,
+    Extension(provider): Extension<Arc<BitcoinDataProvider>>,
+    height: Result<Path<String>, PathRejection>,
+) -> Result<Json<BlockDetail>, ApiError> {
+    let Path(height) = height?;
+    let height: i64 = height
+        .parse()
+        .ok()
+        .filter(|height| *height >= 0)
+        .ok_or_else(|| ApiError::Validation(format!("Invalid block height: {}", height)))?;
+    Ok(Json(find_block_by_height(&pool, &provider, height).await?))
+}
+
+async fn get_block_by_hash(
+    Extension(pool): Extension<Pool>,
+    Extension(provider): Extension<Arc<BitcoinDataProvider>>,
+    hash: Result<Path<String>, PathRejection>,
+) -> Result<Json<BlockDetail>, ApiError> {
+    let Path(hash) = hash?;
+    if !is_hex_hash(&hash) {
+        return Err(ApiError::Validation(format!("Invalid block hash: {}", hash)));
+    }
+    let hash = hash.to_ascii_lowercase();
+    Ok(Json(find_block_by_hash(&pool, &provider, &hash).await?))
+}
+
+async fn find_block_by_height(
+    pool: &Pool,
+    provider: &BitcoinDataProvider,
+    height: i64,
+) -> Result<BlockDetail, ApiError> {
+    let sql = format!("{} m.block_height = $1", BLOCK_DETAIL_SQL);
+    let client = pool.get().await?;
+    if let Some(row) = telemetry::observe_db("find_block_by_height", client.query_opt(&sql, &[&height])).await? {
+        return Ok(BlockDetail::from_row(&row));
+    }
+    // Don't hold the connection while waiting on the provider
+    drop(client);
+    let block = provider.get_block_info(height).await?;
+    Ok(BlockDetail::from_block_info(block))
+}
+
+async fn find_block_by_hash(
+    pool: &Pool,
+    provider: &BitcoinDataProvider,
+    hash: &str,
+) -> Result<BlockDetail, ApiError> {
+    let sql = format!("{} m.block_hash = $1", BLOCK_DETAIL_SQL);
+    let client = pool.get().await?;
+    if let Some(row) = telemetry::observe_db("find_block_by_hash", client.query_opt(&sql, &[&hash])).await? {
+        return Ok(BlockDetail::from_row(&row));
+    }
+    drop(client);
+    let block = provider.get_block_info_by_hash(hash).await?;
+    Ok(BlockDetail::from_block_info(block))
+}
+
+async fn get_transaction(
+    Extension(provider): Extension<Arc<BitcoinDataProvider>>,
+    txid: Result<Path<String>, PathRejection>,
+) -> Result<Json<TransactionDetail>, ApiError> {
+    let Path(txid) = txid?;
+    if !is_hex_hash(&txid) {
+        return Err(ApiError::Validation(format!("Invalid txid: {}", txid)));
+    }
+    let tx = provider.get_transaction(&txid.to_ascii_lowercase()).await?;
+    Ok(Json(tx))
+}
+
+async fn get_address(
+    Extension(pool): Extension<Pool>,
+    Extension(provider): Extension<Arc<BitcoinDataProvider>>,
+    address: Result<Path<String>, PathRejection>,
+    query: Result<Query<AddressQuery>, QueryRejection>,
+) -> Result<Json<AddressInfo>, ApiError> {
+    let Path(address) = address?;
+    let Query(query) = query?;
+    let limit = query.limit.unwrap_or(DEFAULT_ADDRESS_PAGE_LIMIT);
+    if !(1..=MAX_PAGE_LIMIT).contains(&limit) {
+        return Err(ApiError::Validation(format!(
+            "limit must be between 1 and {}",
+            MAX_PAGE_LIMIT
+        )));
+    }
+    let offset = query.offset.unwrap_or(0);
+    if offset < 0 {
+        return Err(ApiError::Validation("offset must not be negative".to_string()));
+    }
+
+    let address = parse_address(&address, provider.network())?.to_string();
+    Ok(Json(find_address(&pool, &provider, &address, limit, offset).await?))
 }
-pub async fn start_server(client: Arc<Client>) -> Result<(), Box<dyn Error>> {
+
+/// Asks the provider first and falls back to the local address index.
+async fn find_address(
+    pool: &Pool,
+    provider: &BitcoinDataProvider,
+    address: &str,
+    limit: i64,
+    offset: i64,
+) -> Result<AddressInfo, ApiError> {
+    if let Some(info) = provider.get_address_info(address, limit, offset).await? {
+        return Ok(info);
+    }
+    Ok(load_address_info(&*pool.get().await?, address, limit, offset).await?)
+}
+
+async fn search(
+    Extension(pool): Extension<Pool>,
+    Extension(provider): Extension<Arc<BitcoinDataProvider>>,
+    params: Result<Query<SearchParams>, QueryRejection>,
+) -> Result<Json<SearchResponse>, ApiError> {
+    let Query(params) = params?;
+    let hit = match classify(&params.q, provider.network())? {
+        SearchQuery::Height(height) => {
+            SearchHit::Block(find_block_by_height(&pool, &provider, height).await?)
+        }
+        // Block hashes and txids look the same, so try the block first
+        SearchQuery::Hash(hash) => match find_block_by_hash(&pool, &provider, &hash).await {
+            Ok(block) => SearchHit::Block(block),
+            Err(ApiError::NotFound(_)) => {
+                match provider.get_transaction(&hash).await.map_err(ApiError::from) {
+                    Ok(tx) => SearchHit::Transaction(tx),
+                    Err(ApiError::NotFound(_)) => {
+                        return Err(ApiError::NotFound(format!(
+                            "No block or transaction matches {}",
+                            hash
+                        )))
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            Err(e) => return Err(e),
+        },
+        SearchQuery::Address(address) => {
+            let address = address.to_string();
+            SearchHit::Address(
+                find_address(&pool, &provider, &address, DEFAULT_ADDRESS_PAGE_LIMIT, 0).await?,
+            )
+        }
+        SearchQuery::Xpub(xpub) => SearchHit::Xpub(xpub),
+    };
+
+    let redirect = match &hit {
+        SearchHit::Block(block) => Some(format!("/blocks/hash/{}", block.hash)),
+        SearchHit::Transaction(tx) => Some(format!("/tx/{}", tx.txid)),
+        SearchHit::Address(info) => Some(format!("/address/{}", info.address)),
+        // There is no xpub endpoint to send the client to
+        SearchHit::Xpub(_) => None,
+    };
+    Ok(Json(SearchResponse {
+        query: params.q.trim().to_string(),
+        redirect,
+        hit,
+    }))
+}
+
+async fn ws_handler(
+    ws: WebSocketUpgrade,
+    Extension(events): Extension<EventBus>,
+    params: Result<Query<TopicParams>, QueryRejection>,
+) -> Result<Response, ApiError> {
+    let Query(params) = params?;
+    let topics = parse_topics(params.topics.as_deref(), &Topic::ALL)?;
+    let receiver = events.subscribe();
+    Ok(ws.on_upgrade(move |socket| stream_events(socket, receiver, topics)))
+}
+
+/// Forwards events on the subscribed topics and handles subscribe/unsubscribe commands
+/// until either side closes the connection.
+async fn stream_events(
+    mut socket: WebSocket,
+    mut receiver: Receiver<EventEnvelope>,
+    mut topics: BTreeSet<Topic>,
+) {
+    loop {
+        tokio::select! {
+            envelope = receiver.recv() => match envelope {
+                Ok(envelope) if topics.contains(&envelope.event.topic()) => {
+                    let Ok(text) = serde_json::to_string(&envelope.event) else { continue };
+                    if socket.send(Message::Text(text)).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("WebSocket client fell behind, dropped {} events", skipped);
+                }
+                Err(RecvError::Closed) => break,
+            },
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    let reply = match serde_json::from_str::<WsCommand>(&text) {
+                        Ok(WsCommand::Subscribe { topics: added }) => {
+                            topics.extend(added);
+                            json!({ "type": "subscribed", "topics": topics })
+                        }
+                        Ok(WsCommand::Unsubscribe { topics: removed }) => {
+                            for topic in &removed {
+                                topics.remove(topic);
+                            }
+                            json!({ "type": "subscribed", "topics": topics })
+                        }
+                        Err(e) => json!({ "type": "error", "message": format!("Invalid command: {}", e) }),
+                    };
+                    if socket.send(Message::Text(reply.to_string())).await.is_err() {
+                        break;
+                    }
+                }
+                // Pings are answered by axum; other frames carry nothing for us
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+/// Server-sent events for clients that only need to follow the chain. Defaults to the
+/// `new_block` and `reorg` topics. A reconnecting client's `Last-Event-ID` replays the buffered
+/// events it missed before live events resume.
+async fn sse_handler(
+    headers: HeaderMap,
+    Extension(events): Extension<EventBus>,
+    params: Result<Query<TopicParams>, QueryRejection>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    let Query(params) = params?;
+    let topics = parse_topics(params.topics.as_deref(), &[Topic::NewBlock, Topic::Reorg])?;
+    let last_id = match headers.get("last-event-id") {
+        Some(value) => value
+            .to_str()
+            .ok()
+            .and_then(|id| id.trim().parse::<u64>().ok())
+            .ok_or_else(|| ApiError::Validation("Last-Event-ID must be an event id".to_string()))?,
+        // Nothing to replay for new clients
+        None => u64::MAX,
+    };
+
+    let (missed, receiver) = events.subscribe_from(last_id);
+    let live = stream::unfold(receiver, |mut receiver| async move {
+        match receiver.recv().await {
+            Ok(envelope) => Some((envelope, receiver)),
+            // Ending the stream makes the client reconnect with its Last-Event-ID and pick the
+            // dropped events up from the history buffer
+            Err(RecvError::Lagged(skipped)) => {
+                warn!("SSE client fell behind by {} events, closing stream", skipped);
+                None
+            }
+            Err(RecvError::Closed) => None,
+        }
+    });
+    let stream = stream::iter(missed)
+        .chain(live)
+        .filter(move |envelope| future::ready(topics.contains(&envelope.event.topic())))
+        .map(|envelope| {
+            let event = Event::default()
+                .id(envelope.id.to_string())
+                .event(envelope.event.topic().as_str());
+            Ok(event.json_data(&envelope.event).unwrap_or_default())
+        });
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+/// Liveness probe: answers as long as the process is serving requests.
+async fn healthz() -> Json<serde_json::Value> {
+    Json(json!({ "status": "ok" }))
+}
+
+/// Readiness probe reporting each dependency, with 503 when any of them is degraded.
+async fn readyz(
+    Extension(pool): Extension<Pool>,
+    Extension(provider): Extension<Arc<BitcoinDataProvider>>,
+    Extension(config): Extension<HealthConfig>,
+) -> (StatusCode, Json<ReadinessReport>) {
+    let report = check_readiness(&pool, &provider, &config).await;
+    let status = if report.is_ready() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(report))
+}
+
+/// Prometheus scrape endpoint.
+async fn get_metrics() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        telemetry::render(),
+    )
+}
+
+/// Records the latency of every request under its route template so path parameters don't
+/// create a series per block or address.
+async fn track_http(request: Request, next: Next) -> Response {
+    let method = request.method().clone();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched", |path| path.as_str())
+        .to_string();
+    let started = Instant::now();
+    let response = next.run(request).await;
+    telemetry::record_http_request(
+        method.as_str(),
+        &route,
+        response.status().as_u16(),
+        started.elapsed(),
+    );
+    response
+}
+
+/// Parses a comma-separated topic list, falling back to `default` when none is given.
+fn parse_topics(list: Option<&str>, default: &[Topic]) -> Result<BTreeSet<Topic>, ApiError> {
+    match list {
+        Some(list) => list
+            .split(',')
+            .map(|topic| topic.trim().parse())
+            .collect::<Result<BTreeSet<Topic>, String>>()
+            .map_err(ApiError::Validation),
+        None => Ok(default.iter().copied().collect()),
+    }
+}
+
+/// Block hashes and txids are 32 bytes rendered as 64 hex characters.
+fn is_hex_hash(value: &str) -> bool {
+    value.len() == 64 && value.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+pub async fn start_server(
+    pool: Pool,
+    provider: Arc<BitcoinDataProvider>,
+    events: EventBus,
+    health_config: HealthConfig,
+) -> Result<(), Box<dyn Error>> {
     // Configure CORS
     let cors = CorsLayer::new()
         .allow_methods([Method::GET])
         .allow_origin(Any)
-        .allow_headers(Any);
+        .allow_headers(Any)
+        .expose_headers([header::HeaderName::from_static(NEXT_CURSOR_HEADER)]);
 
     let app = Router::new()
         .route("/transactions", get(get_transactions))
         .route("/blockchain_metrics", get(get_blockchain_metrics))
-        .layer(Extension(client))
+        .route("/block_fee_stats", get(get_block_fee_stats))
+        .route("/mempool/history", get(get_mempool_history))
+        .route("/difficulty", get(get_difficulty_epochs))
+        .route("/chain/projections", get(get_chain_projections))
+        .route("/hashrate", get(get_hashrate))
+        .route("/blocks/:height", get(get_block_by_height))
+        .route("/blocks/hash/:hash", get(get_block_by_hash))
+        .route("/tx/:txid", get(get_transaction))
+        .route("/address/:address", get(get_address))
+        .route("/search", get(search))
+        .route("/ws", get(ws_handler))
+        .route("/events", get(sse_handler))
+        .route("/metrics", get(get_metrics))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route_layer(middleware::from_fn(track_http))
+        .layer(Extension(pool))
+        .layer(Extension(provider))
+        .layer(Extension(events))
+        .layer(Extension(health_config))
         .layer(cors);
 
     let listener = TcpListener::bind("0.0.0.0:3001").await
@@ -0,0 +1,165 @@
+use lazy_static::lazy_static;
+use log::error;
+use prometheus::{
+    register_gauge, register_histogram_vec, register_int_counter, register_int_counter_vec,
+    register_int_gauge, Encoder, Gauge, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    TextEncoder,
+};
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+lazy_static! {
+    static ref CHAIN_TIP_HEIGHT: IntGauge = register_int_gauge!(
+        "explorer_chain_tip_height",
+        "Height of the best block reported by the data provider"
+    )
+    .unwrap();
+    static ref INGESTED_HEIGHT: IntGauge = register_int_gauge!(
+        "explorer_ingested_height",
+        "Height of the last block stored by the ingestion loop"
+    )
+    .unwrap();
+    static ref INGESTION_LAG_BLOCKS: IntGauge = register_int_gauge!(
+        "explorer_ingestion_lag_blocks",
+        "Blocks between the chain tip and the last ingested block"
+    )
+    .unwrap();
+    static ref INGESTION_LAG_SECONDS: Gauge = register_gauge!(
+        "explorer_ingestion_lag_seconds",
+        "Seconds ingestion has been behind the chain tip, 0 when caught up"
+    )
+    .unwrap();
+    static ref PROVIDER_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "explorer_provider_requests_total",
+        "Data provider requests by backend, method and outcome",
+        &["backend", "method", "outcome"]
+    )
+    .unwrap();
+    static ref PROVIDER_REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "explorer_provider_request_duration_seconds",
+        "Data provider request latency by backend and method",
+        &["backend", "method"]
+    )
+    .unwrap();
+    static ref BLOCKCHAIN_INFO_RATE_LIMITED: IntCounter = register_int_counter!(
+        "explorer_blockchain_info_rate_limited_total",
+        "Responses from blockchain.info with status 429"
+    )
+    .unwrap();
+    static ref DB_QUERY_DURATION: HistogramVec = register_histogram_vec!(
+        "explorer_db_query_duration_seconds",
+        "Postgres query latency by query",
+        &["query"]
+    )
+    .unwrap();
+    static ref HTTP_REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "explorer_http_request_duration_seconds",
+        "HTTP request latency by method, route and status",
+        &["method", "route", "status"]
+    )
+    .unwrap();
+    /// When ingestion first fell behind the current tip
+    static ref BEHIND_SINCE: Mutex<Option<Instant>> = Mutex::new(None);
+    /// When the provider last reported the chain tip
+    static ref CHAIN_TIP_SEEN: Mutex<Option<Instant>> = Mutex::new(None);
+    /// When a provider request last failed
+    static ref PROVIDER_FAILED_AT: Mutex<Option<Instant>> = Mutex::new(None);
+}
+
+pub fn record_chain_tip(height: i64) {
+    CHAIN_TIP_HEIGHT.set(height);
+    *CHAIN_TIP_SEEN.lock().unwrap() = Some(Instant::now());
+    update_ingestion_lag();
+}
+
+pub fn record_ingested_height(height: i64) {
+    INGESTED_HEIGHT.set(height);
+    update_ingestion_lag();
+}
+
+fn update_ingestion_lag() {
+    let behind = (CHAIN_TIP_HEIGHT.get() - INGESTED_HEIGHT.get()).max(0);
+    INGESTION_LAG_BLOCKS.set(behind);
+    let mut behind_since = BEHIND_SINCE.lock().unwrap();
+    if behind > 0 {
+        behind_since.get_or_insert_with(Instant::now);
+    } else {
+        *behind_since = None;
+    }
+}
+
+/// The last chain tip the provider reported, if that was at most `max_age` ago and no provider
+/// request has failed since.
+pub fn recent_chain_tip(max_age: Duration) -> Option<i64> {
+    let seen = (*CHAIN_TIP_SEEN.lock().unwrap())?;
+    let failed_since = PROVIDER_FAILED_AT
+        .lock()
+        .unwrap()
+        .is_some_and(|failed| failed >= seen);
+    if seen.elapsed() > max_age || failed_since {
+        return None;
+    }
+    Some(CHAIN_TIP_HEIGHT.get())
+}
+
+pub fn ingested_height() -> i64 {
+    INGESTED_HEIGHT.get()
+}
+
+pub fn ingestion_lag_blocks() -> i64 {
+    INGESTION_LAG_BLOCKS.get()
+}
+
+pub fn ingestion_lag_seconds() -> f64 {
+    BEHIND_SINCE
+        .lock()
+        .unwrap()
+        .map_or(0.0, |since| since.elapsed().as_secs_f64())
+}
+
+pub fn record_provider_request(backend: &str, method: &str, succeeded: bool, elapsed: Duration) {
+    let outcome = if succeeded { "ok" } else { "error" };
+    if !succeeded {
+        *PROVIDER_FAILED_AT.lock().unwrap() = Some(Instant::now());
+    }
+    PROVIDER_REQUESTS
+        .with_label_values(&[backend, method, outcome])
+        .inc();
+    PROVIDER_REQUEST_DURATION
+        .with_label_values(&[backend, method])
+        .observe(elapsed.as_secs_f64());
+}
+
+pub fn record_rate_limited() {
+    BLOCKCHAIN_INFO_RATE_LIMITED.inc();
+}
+
+pub fn record_http_request(method: &str, route: &str, status: u16, elapsed: Duration) {
+    HTTP_REQUEST_DURATION
+        .with_label_values(&[method, route, &status.to_string()])
+        .observe(elapsed.as_secs_f64());
+}
+
+/// Runs a database call and records its latency under `query`.
+pub async fn observe_db<T, E>(
+    query: &'static str,
+    call: impl Future<Output = Result<T, E>>,
+) -> Result<T, E> {
+    let timer = DB_QUERY_DURATION.with_label_values(&[query]).start_timer();
+    let result = call.await;
+    timer.observe_duration();
+    result
+}
+
+/// Renders every registered metric in the Prometheus text format.
+pub fn render() -> String {
+    // Registered lazily, so touch it to export 0 before the first 429
+    lazy_static::initialize(&BLOCKCHAIN_INFO_RATE_LIMITED);
+    INGESTION_LAG_SECONDS.set(ingestion_lag_seconds());
+    let mut buffer = Vec::new();
+    if let Err(e) = TextEncoder::new().encode(&prometheus::gather(), &mut buffer) {
+        error!("Failed to encode metrics: {}", e);
+    }
+    String::from_utf8(buffer).unwrap_or_default()
+}
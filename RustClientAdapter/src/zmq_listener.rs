@@ -0,0 +1,263 @@
+use crate::config::ZmqConfig;
+use crate::events::{ChainEvent, EventBus};
+use bitcoincore_rpc::bitcoin::consensus::deserialize;
+use bitcoincore_rpc::bitcoin::Transaction;
+use log::{error, info, warn};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// Pause before reconnecting after a socket error
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// Unmatched `rawtx` and `sequence` messages kept while waiting for their counterpart
+const MATCH_BACKLOG: usize = 4096;
+/// `sequence` label of a transaction added to the mempool
+const SEQUENCE_MEMPOOL_ADD: u8 = b'A';
+
+/// Subscribes to the configured bitcoind ZMQ endpoints on background threads. Block
+/// announcements wake the ingestion loop through `block_notify`; relayed transactions are
+/// published as `mempool_tx` events. bitcoind also sends `rawtx` for every transaction of a
+/// connected block, so with a `sequence` endpoint only transactions it reports as added to the
+/// mempool are published. Without one, every non-coinbase transaction seen is published.
+pub fn spawn_zmq_listeners(config: &ZmqConfig, block_notify: Arc<Notify>, events: EventBus) {
+    let context = zmq::Context::new();
+
+    if let Some(endpoint) = &config.hashblock {
+        spawn_listener(
+            context.clone(),
+            endpoint.clone(),
+            "hashblock",
+            config.quiet_timeout,
+            move |body| {
+                // bitcoind sends the hash in the usual display byte order
+                info!("ZMQ announced block {}", to_hex(body));
+                block_notify.notify_one();
+            },
+        );
+    }
+
+    if let Some(endpoint) = &config.rawtx {
+        let matcher = config
+            .sequence
+            .as_ref()
+            .map(|_| Arc::new(Mutex::new(MempoolMatcher::default())));
+
+        if let (Some(sequence), Some(matcher)) = (&config.sequence, &matcher) {
+            let matcher = matcher.clone();
+            let events = events.clone();
+            spawn_listener(
+                context.clone(),
+                sequence.clone(),
+                "sequence",
+                config.quiet_timeout,
+                move |body| {
+                    if let Some(txid) = mempool_addition(body) {
+                        if let Some(event) = matcher.lock().unwrap().added(txid) {
+                            events.publish(event);
+                        }
+                    }
+                },
+            );
+        }
+
+        spawn_listener(
+            context,
+            endpoint.clone(),
+            "rawtx",
+            config.quiet_timeout,
+            move |body| match deserialize::<Transaction>(body) {
+                Ok(tx) if tx.is_coinbase() => {}
+                Ok(tx) => {
+                    let txid = tx.compute_txid().to_string();
+                    let event = ChainEvent::MempoolTx {
+                        txid: txid.clone(),
+                        vsize: tx.vsize() as u64,
+                        output_value: tx.output.iter().map(|output| output.value.to_sat()).sum(),
+                    };
+                    let event = match &matcher {
+                        Some(matcher) => matcher.lock().unwrap().received(txid, event),
+                        None => Some(event),
+                    };
+                    if let Some(event) = event {
+                        events.publish(event);
+                    }
+                }
+                Err(e) => warn!("Failed to decode ZMQ rawtx message: {}", e),
+            },
+        );
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Txid of a `sequence` message announcing a mempool addition. The body is the hash in display
+/// byte order, a one byte label and, for mempool changes, an 8 byte mempool sequence number.
+fn mempool_addition(body: &[u8]) -> Option<String> {
+    match body.get(32) {
+        Some(&SEQUENCE_MEMPOOL_ADD) => Some(to_hex(&body[..32])),
+        _ => None,
+    }
+}
+
+/// Pairs decoded `rawtx` transactions with `sequence` mempool additions. bitcoind sends both
+/// messages together, so only a short backlog of either side is kept; transactions that only
+/// arrive as part of a block never get a mempool addition and fall out of the backlog.
+#[derive(Default)]
+struct MempoolMatcher {
+    transactions: HashMap<String, ChainEvent>,
+    transaction_order: VecDeque<String>,
+    additions: HashSet<String>,
+    addition_order: VecDeque<String>,
+}
+
+impl MempoolMatcher {
+    /// Returns the event to publish once the transaction was also added to the mempool.
+    fn received(&mut self, txid: String, event: ChainEvent) -> Option<ChainEvent> {
+        if self.additions.remove(&txid) {
+            return Some(event);
+        }
+        if let Some(evicted) = push_bounded(&mut self.transaction_order, txid.clone()) {
+            self.transactions.remove(&evicted);
+        }
+        self.transactions.insert(txid, event);
+        None
+    }
+
+    /// Returns the event to publish once the added transaction itself has arrived.
+    fn added(&mut self, txid: String) -> Option<ChainEvent> {
+        if let Some(event) = self.transactions.remove(&txid) {
+            return Some(event);
+        }
+        if let Some(evicted) = push_bounded(&mut self.addition_order, txid.clone()) {
+            self.additions.remove(&evicted);
+        }
+        self.additions.insert(txid);
+        None
+    }
+}
+
+/// Appends `txid` and returns the oldest entry once the backlog is full.
+fn push_bounded(order: &mut VecDeque<String>, txid: String) -> Option<String> {
+    order.push_back(txid);
+    if order.len() > MATCH_BACKLOG {
+        order.pop_front()
+    } else {
+        None
+    }
+}
+
+fn spawn_listener<F>(
+    context: zmq::Context,
+    endpoint: String,
+    topic: &'static str,
+    quiet_timeout: Duration,
+    mut handle: F,
+) where
+    F: FnMut(&[u8]) + Send + 'static,
+{
+    thread::Builder::new()
+        .name(format!("zmq-{}", topic))
+        .spawn(move || loop {
+            if let Err(e) = listen(&context, &endpoint, topic, quiet_timeout, &mut handle) {
+                error!("ZMQ {} subscription to {} failed: {}", topic, endpoint, e);
+                thread::sleep(RECONNECT_DELAY);
+            }
+        })
+        .expect("Failed to spawn ZMQ listener thread");
+}
+
+/// Passes message bodies to `handle` until the socket stays quiet for `quiet_timeout`, after
+/// which the caller reconnects. Polling keeps ingestion going in the meantime.
+fn listen<F>(
+    context: &zmq::Context,
+    endpoint: &str,
+    topic: &str,
+    quiet_timeout: Duration,
+    handle: &mut F,
+) -> Result<(), zmq::Error>
+where
+    F: FnMut(&[u8]),
+{
+    let socket = context.socket(zmq::SUB)?;
+    socket.set_tcp_keepalive(1)?;
+    socket.set_subscribe(topic.as_bytes())?;
+    socket.connect(endpoint)?;
+    info!("Subscribed to ZMQ {} at {}", topic, endpoint);
+
+    loop {
+        if socket.poll(zmq::POLLIN, quiet_timeout.as_millis() as i64)? == 0 {
+            warn!(
+                "No ZMQ {} message from {} in {} seconds, reconnecting",
+                topic,
+                endpoint,
+                quiet_timeout.as_secs()
+            );
+            return Ok(());
+        }
+        // Messages are [topic, body, sequence number]
+        let frames = socket.recv_multipart(0)?;
+        match frames.get(1) {
+            Some(body) => handle(body),
+            None => warn!("Ignoring ZMQ {} message without a body", topic),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mempool_tx(txid: &str) -> ChainEvent {
+        ChainEvent::MempoolTx {
+            txid: txid.to_string(),
+            vsize: 141,
+            output_value: 50_000,
+        }
+    }
+
+    #[test]
+    fn publishes_transactions_once_both_messages_arrived() {
+        let mut matcher = MempoolMatcher::default();
+        assert!(matcher.received("aa".to_string(), mempool_tx("aa")).is_none());
+        assert!(matcher.added("aa".to_string()).is_some());
+
+        assert!(matcher.added("bb".to_string()).is_none());
+        assert!(matcher.received("bb".to_string(), mempool_tx("bb")).is_some());
+
+        // Each pair is only published once
+        assert!(matcher.added("aa".to_string()).is_none());
+        assert!(matcher.received("bb".to_string(), mempool_tx("bb")).is_none());
+    }
+
+    #[test]
+    fn block_transactions_without_a_mempool_addition_are_dropped() {
+        let mut matcher = MempoolMatcher::default();
+        for i in 0..=MATCH_BACKLOG {
+            assert!(matcher.received(i.to_string(), mempool_tx(&i.to_string())).is_none());
+        }
+        assert_eq!(matcher.transactions.len(), MATCH_BACKLOG);
+        // The oldest one fell out of the backlog, so a late addition finds nothing
+        assert!(matcher.added("0".to_string()).is_none());
+        assert!(matcher.added("1".to_string()).is_some());
+    }
+
+    #[test]
+    fn only_mempool_additions_are_read_from_sequence_messages() {
+        let mut body = vec![0xab; 32];
+        body.push(b'A');
+        body.extend_from_slice(&7u64.to_le_bytes());
+        assert_eq!(mempool_addition(&body), Some("ab".repeat(32)));
+
+        body[32] = b'R';
+        assert_eq!(mempool_addition(&body), None);
+        // Block connected messages carry no mempool sequence number
+        body.truncate(32);
+        body.push(b'C');
+        assert_eq!(mempool_addition(&body), None);
+        assert_eq!(mempool_addition(&[0xab; 16]), None);
+    }
+}